@@ -0,0 +1,528 @@
+//! Template cache
+//!
+//! Downloaded templates are kept in the data directory, as the archive and
+//! the directory it was extracted to. `cache.toml` records which template
+//! each of them is and when it was fetched.
+//!
+//! Downloads and extractions which are still going on live next to them, as
+//! `.partial` files and `.partial-<pid>` directories. Cleaning leaves those
+//! alone until they have not changed for a while.
+
+use std::path::{Path, PathBuf};
+
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+
+use crate::cli::config::TemplateConfig;
+use crate::cli::error::Error;
+use crate::cli::http::HttpClient;
+use crate::cli::{config, fs, github, lock, template, verify};
+
+/// Name of the cache index in the data directory
+pub const FILE_NAME: &str = "cache.toml";
+
+/// How long a partial download or extraction may go unchanged before it is
+/// taken to be left over from an interrupted one, in seconds
+const IN_PROGRESS: u64 = 10 * 60;
+
+/// The cache sub-commands
+#[derive(Debug, Clone)]
+pub enum CacheCommand {
+    /// List the cached templates
+    List,
+    /// Remove all but the `keep` most recently fetched templates
+    Clean { keep: usize },
+    /// Download a release of the project template ahead of time
+    Prefetch { version: VersionReq, pre: bool },
+    /// Print where templates are cached
+    Path,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Entry {
+    /// When the template was fetched, in seconds since the unix epoch
+    pub fetched: u64,
+    pub template: TemplateConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Index {
+    #[serde(default)]
+    pub templates: Vec<Entry>,
+}
+
+/// A cached template, or a file in the cache which no template accounts for
+pub struct Cached {
+    pub template: Option<TemplateConfig>,
+    pub paths: Vec<PathBuf>,
+    pub size: u64,
+    pub fetched: u64,
+}
+
+/// Where templates are cached
+pub fn dir() -> Result<PathBuf, Error> {
+    match config::dir() {
+        Some(dir) => Ok(dir.data_dir().to_path_buf()),
+        None => Err(Error::Config("cannot find data path".to_string())),
+    }
+}
+
+/// Read the cache index kept in `dir`, a missing index is an empty one
+fn read_from(dir: &Path) -> Result<Index, Error> {
+    let path = dir.join(FILE_NAME);
+    if !path.exists() {
+        return Ok(Index::default());
+    }
+    let toml_string = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+    toml::from_str(&toml_string).map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))
+}
+
+/// Write the cache index kept in `dir`
+fn write_to(dir: &Path, index: &Index) -> Result<(), Error> {
+    let toml_string = toml::to_string(index).map_err(|e| Error::Config(e.to_string()))?;
+    std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    let path = dir.join(FILE_NAME);
+    std::fs::write(&path, toml_string).map_err(|e| Error::io(&path, e))
+}
+
+/// Remember a template which has just been added to the cache in `dir`
+pub fn record(dir: &Path, template: &TemplateConfig) -> Result<(), Error> {
+    let mut index = read_from(dir)?;
+    index
+        .templates
+        .retain(|entry| entry.template.extracted != template.extracted);
+    index.templates.push(Entry {
+        template: template.clone(),
+        fetched: template::now(),
+    });
+    write_to(dir, &index)
+}
+
+/// Check the archive of a cached template has not changed since it was
+/// downloaded, returning its checksum. Archives cached by older versions of
+/// leap have their checksum recorded now.
+pub fn verify(dir: &Path, template: &TemplateConfig) -> Result<String, Error> {
+    let compressed = dir.join(&template.compressed);
+    let recorded = Some(template.hash.clone())
+        .filter(|hash| verify::is_checksum(hash))
+        .or_else(|| {
+            read_from(dir)
+                .ok()?
+                .templates
+                .into_iter()
+                .map(|entry| entry.template)
+                .find(|cached| {
+                    cached.compressed == template.compressed && verify::is_checksum(&cached.hash)
+                })
+                .map(|cached| cached.hash)
+        });
+    if let Some(hash) = recorded {
+        verify::cached(&compressed, &hash)?;
+        return Ok(hash);
+    }
+
+    log::info!("recording the checksum of {}", compressed.display());
+    let data = std::fs::read(&compressed).map_err(|e| Error::io(&compressed, e))?;
+    let hash = lock::hash(&data);
+    record(
+        dir,
+        &TemplateConfig {
+            hash: hash.clone(),
+            ..template.clone()
+        },
+    )?;
+    Ok(hash)
+}
+
+/// Everything in the cache, most recently fetched first
+pub fn list() -> Result<Vec<Cached>, Error> {
+    list_in(&dir()?, config::config()?.template)
+}
+
+/// Everything in the cache kept in `dir`, where `current` is the template
+/// the config points at
+fn list_in(dir: &Path, current: TemplateConfig) -> Result<Vec<Cached>, Error> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut templates = read_from(dir)?.templates;
+    // The release the config points at may predate the index
+    if !templates
+        .iter()
+        .any(|entry| entry.template.extracted == current.extracted)
+    {
+        let fetched = modified(&dir.join(&current.compressed));
+        templates.push(Entry {
+            template: current,
+            fetched,
+        });
+    }
+
+    let mut cached = Vec::new();
+    let mut accounted = vec![dir.join(FILE_NAME), dir.join(github::ETAG_DIR)];
+    for entry in templates {
+        let paths: Vec<PathBuf> = [&entry.template.compressed, &entry.template.extracted]
+            .iter()
+            .filter(|name| !name.is_empty())
+            .map(|name| dir.join(name))
+            .filter(|path| path.exists())
+            .collect();
+        if paths.is_empty() {
+            continue;
+        }
+        accounted.extend(paths.iter().cloned());
+        cached.push(Cached {
+            size: paths.iter().map(|path| size(path)).sum(),
+            template: Some(entry.template),
+            paths,
+            fetched: entry.fetched,
+        });
+    }
+
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let path = entry?.path();
+        if !accounted.contains(&path) {
+            cached.push(Cached {
+                template: None,
+                size: size(&path),
+                fetched: modified(&path),
+                paths: vec![path],
+            });
+        }
+    }
+
+    cached.sort_by_key(|cached| std::cmp::Reverse(cached.fetched));
+    Ok(cached)
+}
+
+/// Remove all but the `keep` most recently fetched templates, returning what was removed
+pub fn clean(keep: usize) -> Result<Vec<Cached>, Error> {
+    clean_in(&dir()?, config::config()?.template, keep, template::now())
+}
+
+/// Clean the cache kept in `dir` at the time `now`
+fn clean_in(
+    dir: &Path,
+    current: TemplateConfig,
+    keep: usize,
+    now: u64,
+) -> Result<Vec<Cached>, Error> {
+    let mut kept = 0;
+    let mut removing = Vec::new();
+    for cached in list_in(dir, current)? {
+        match &cached.template {
+            Some(_) if kept < keep => kept += 1,
+            None if cached.paths.iter().any(|path| in_progress(path, now)) => {}
+            _ => removing.push(cached),
+        }
+    }
+
+    let mut removed = Vec::new();
+    let mut failed = None;
+    for cached in removing {
+        match remove(&cached.paths) {
+            Ok(()) => removed.push(cached),
+            Err(e) => {
+                failed = Some(e);
+                break;
+            }
+        }
+    }
+
+    // Forget what is gone, even when something could not be removed
+    let mut index = read_from(dir)?;
+    index.templates.retain(|entry| {
+        [&entry.template.compressed, &entry.template.extracted]
+            .iter()
+            .any(|name| !name.is_empty() && dir.join(name).exists())
+    });
+    write_to(dir, &index)?;
+
+    match failed {
+        Some(e) => Err(e),
+        None => Ok(removed),
+    }
+}
+
+/// Remove files and directories
+fn remove(paths: &[PathBuf]) -> Result<(), Error> {
+    for path in paths {
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+        removed.map_err(|e| Error::io(path, e))?;
+    }
+    Ok(())
+}
+
+/// Is `path` a download or extraction which may still be going on at the time `now`?
+fn in_progress(path: &Path, now: u64) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let partial = name.ends_with(".partial")
+        || name.ends_with(".partial.validator")
+        || name.contains(".partial-");
+    partial && now.saturating_sub(modified(path)) < IN_PROGRESS
+}
+
+/// The newest cached template from `source` matching `requirement`, for
+/// working offline
+pub fn newest(
+    source: &str,
+    requirement: Option<&VersionReq>,
+    pre: bool,
+) -> Result<Option<(TemplateConfig, PathBuf)>, Error> {
+    let current = config::config()?.template;
+    newest_in(&dir()?, current, source, requirement, pre)
+}
+
+/// The newest template from `source` in the cache kept in `dir`
+fn newest_in(
+    dir: &Path,
+    current: TemplateConfig,
+    source: &str,
+    requirement: Option<&VersionReq>,
+    pre: bool,
+) -> Result<Option<(TemplateConfig, PathBuf)>, Error> {
+    let newest = list_in(dir, current)?
+        .into_iter()
+        // Oldest first so the most recently fetched copy of a version wins
+        .rev()
+        .filter_map(|cached| cached.template)
+        .filter(|template| template.source == source && dir.join(&template.extracted).is_dir())
+        .filter(|template| pre || !template.version.is_prerelease())
+        .filter(|template| {
+            requirement.is_none_or(|requirement| requirement.matches(&template.version))
+        })
+        // What was extracted cannot be trusted once its archive is gone
+        .filter(|template| {
+            let checked = template.compressed.is_empty() || dir.join(&template.compressed).exists();
+            if !checked {
+                log::warn!(
+                    "{} {} is not used because its archive {} is missing, so it cannot be checked",
+                    template.source,
+                    template.version,
+                    template.compressed
+                );
+            }
+            checked
+        })
+        .max_by(|a, b| a.version.cmp(&b.version));
+
+    match newest {
+        Some(template) => {
+            if !template.compressed.is_empty() {
+                verify(dir, &template)?;
+            }
+            let extracted = dir.join(&template.extracted);
+            let path = fs::root(&extracted).map_err(|e| Error::io(&extracted, e))?;
+            Ok(Some((template, path)))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Download a release of the project template into the cache
+pub fn prefetch(
+    client: &dyn HttpClient,
+    version: &VersionReq,
+    pre: bool,
+) -> Result<(TemplateConfig, PathBuf), Error> {
+    let config = config::settings()?;
+    let versions = github::get_template_versions(
+        client,
+        &config.github.api_url,
+        github::PROJECT_TEMPLATE_REPOSITORY,
+    )?;
+    let tag = github::latest(&versions, Some(version), pre).ok_or_else(|| {
+        Error::Template(format!(
+            "the project template has no release matching {}",
+            version
+        ))
+    })?;
+    template::fetch(
+        client,
+        &config.github.api_url,
+        github::PROJECT_TEMPLATE_REPOSITORY,
+        tag,
+    )
+}
+
+/// The size of a file, or everything below a directory
+pub fn size(path: &Path) -> u64 {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return 0,
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    match std::fs::read_dir(path) {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .map(|entry| size(&entry.path()))
+            .sum(),
+        Err(_) => 0,
+    }
+}
+
+/// When a path was last modified, in seconds since the unix epoch
+fn modified(path: &Path) -> u64 {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format a number of bytes for people
+pub fn human_size(bytes: u64) -> String {
+    let units = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+    use semver::Version;
+
+    /// Cache `version` of a template in `dir` as `name`, fetched at `fetched`
+    fn cache(dir: &Path, name: &str, version: &str, fetched: u64) -> Entry {
+        let data = format!("{} archive", name);
+        std::fs::create_dir_all(dir.join(name).join("project")).unwrap();
+        std::fs::write(dir.join(name).join("project/Cargo.toml"), "").unwrap();
+        std::fs::write(dir.join(format!("{}.zip", name)), &data).unwrap();
+        Entry {
+            fetched,
+            template: TemplateConfig {
+                hash: lock::hash(data.as_bytes()),
+                version: Version::parse(version).unwrap(),
+                compressed: format!("{}.zip", name),
+                extracted: name.to_string(),
+                source: "owner/template".to_string(),
+                tag: format!("v{}", version),
+                sha: String::new(),
+            },
+        }
+    }
+
+    /// A cache in `dir` with three releases of a template and two files
+    /// which are not templates
+    fn fixture(dir: &Path) -> TemplateConfig {
+        let templates = vec![
+            cache(dir, "one", "0.1.0", 100),
+            cache(dir, "two", "0.2.0", 300),
+            cache(dir, "three", "0.3.0-beta.1", 200),
+        ];
+        write_to(dir, &Index { templates }).unwrap();
+        std::fs::write(dir.join("stray.zip"), "stray").unwrap();
+        std::fs::write(dir.join("four.zip.partial"), "four").unwrap();
+        TemplateConfig {
+            extracted: "not-cached".to_string(),
+            compressed: String::new(),
+            ..TemplateConfig::default()
+        }
+    }
+
+    fn names(cached: &[Cached]) -> Vec<String> {
+        cached
+            .iter()
+            .map(|cached| match &cached.template {
+                Some(template) => template.extracted.clone(),
+                None => cached.paths[0]
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn list_works() {
+        let dir = scratch("cache-list");
+        let current = fixture(&dir);
+        let listed = list_in(&dir, current).unwrap();
+        let (templates, others): (Vec<Cached>, Vec<Cached>) = listed
+            .into_iter()
+            .partition(|cached| cached.template.is_some());
+        assert_eq!(names(&templates), ["two", "three", "one"]);
+        assert_eq!(templates[0].paths, [dir.join("two.zip"), dir.join("two")]);
+        assert_eq!(templates[0].size, "two archive".len() as u64);
+        let mut others = names(&others);
+        others.sort();
+        assert_eq!(others, ["four.zip.partial", "stray.zip"]);
+    }
+
+    #[test]
+    fn clean_works() {
+        let dir = scratch("cache-clean");
+        let current = fixture(&dir);
+        let now = template::now();
+        let mut removed = names(&clean_in(&dir, current.clone(), 1, now).unwrap());
+        removed.sort();
+        assert_eq!(removed, ["one", "stray.zip", "three"]);
+        assert!(dir.join("two").is_dir() && !dir.join("one").exists());
+        // A download which may still be going on is left alone
+        assert!(dir.join("four.zip.partial").exists());
+        let index = read_from(&dir).unwrap();
+        assert_eq!(index.templates.len(), 1);
+        assert_eq!(index.templates[0].template.extracted, "two");
+
+        let later = now + IN_PROGRESS;
+        let removed = clean_in(&dir, current, 0, later).unwrap();
+        assert_eq!(names(&removed), ["four.zip.partial", "two"]);
+        assert!(read_from(&dir).unwrap().templates.is_empty());
+    }
+
+    #[test]
+    fn newest_works() {
+        let dir = scratch("cache-newest");
+        let current = fixture(&dir);
+        let newest = |requirement: Option<&str>, pre| {
+            let requirement = requirement.map(|req| VersionReq::parse(req).unwrap());
+            newest_in(
+                &dir,
+                current.clone(),
+                "owner/template",
+                requirement.as_ref(),
+                pre,
+            )
+            .map(|newest| newest.map(|(template, path)| (template.version.to_string(), path)))
+        };
+        assert_eq!(
+            newest(None, false).unwrap(),
+            Some(("0.2.0".to_string(), dir.join("two/project")))
+        );
+        assert_eq!(newest(None, true).unwrap().unwrap().0, "0.3.0-beta.1");
+        assert_eq!(newest(Some("^0.1"), false).unwrap().unwrap().0, "0.1.0");
+        assert_eq!(newest(Some("^0.4"), false).unwrap(), None);
+
+        // Without its archive, what was extracted cannot be checked
+        std::fs::remove_file(dir.join("two.zip")).unwrap();
+        assert_eq!(newest(None, false).unwrap().unwrap().0, "0.1.0");
+        std::fs::write(dir.join("one.zip"), "tampered").unwrap();
+        assert!(matches!(newest(None, false), Err(Error::Archive { .. })));
+    }
+
+    #[test]
+    fn human_size_works() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+    }
+}
@@ -0,0 +1,186 @@
+//! Build leap projects with cargo
+
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use serde_json::Value;
+
+use crate::cli::error::Error;
+use crate::cli::output;
+
+/// The outcome of a cargo build
+pub struct Build {
+    pub success: bool,
+    pub executable: Option<PathBuf>,
+}
+
+/// Build the project at `root`, streaming compiler messages to stderr
+pub fn build(root: &Path, release: bool) -> Result<Build, Error> {
+    let manifest = root.join("Cargo.toml");
+    let mut command = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string()));
+    command
+        .arg("build")
+        .arg(if output::color() {
+            "--message-format=json-diagnostic-rendered-ansi"
+        } else {
+            "--message-format=json"
+        })
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .current_dir(root)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    if release {
+        command.arg("--release");
+    }
+
+    log::info!("running: {:?}", &command);
+    let mut child = command
+        .spawn()
+        .map_err(|e| Error::Project(format!("unable to run cargo because {}", e)))?;
+    let stdout = child.stdout.take().expect("cargo stdout is piped");
+
+    let mut messages = Messages::new(&manifest);
+    for line in BufReader::new(stdout).lines() {
+        if let Some(rendered) = messages.read(&line?) {
+            eprint!("{}", rendered);
+            std::io::stderr().flush()?;
+        }
+    }
+
+    let status = child.wait()?;
+    Ok(Build {
+        success: messages.success && status.success(),
+        executable: messages.executable(package_name(root).as_deref()),
+    })
+}
+
+/// What cargo says about a build, from its JSON messages
+struct Messages {
+    /// The manifest of the project, whose executables are wanted
+    manifest: PathBuf,
+    executables: Vec<PathBuf>,
+    success: bool,
+}
+
+impl Messages {
+    fn new(manifest: &Path) -> Messages {
+        Messages {
+            manifest: canonical(manifest),
+            executables: Vec::new(),
+            success: false,
+        }
+    }
+
+    /// Take in a line cargo wrote, returning a diagnostic to show
+    fn read(&mut self, line: &str) -> Option<String> {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_) => {
+                // cargo passes through anything build scripts print
+                log::info!("cargo: {}", line);
+                return None;
+            }
+        };
+
+        match message["reason"].as_str() {
+            Some("compiler-message") => {
+                return message["message"]["rendered"].as_str().map(String::from);
+            }
+            Some("compiler-artifact") => {
+                let from_root = message["manifest_path"]
+                    .as_str()
+                    .is_some_and(|path| canonical(Path::new(path)) == self.manifest);
+                if let (true, Some(executable)) = (from_root, message["executable"].as_str()) {
+                    self.executables.push(PathBuf::from(executable));
+                }
+            }
+            Some("build-finished") => {
+                self.success = message["success"].as_bool().unwrap_or(false);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// The executable named after `package`, or else the first one built
+    fn executable(&self, package: Option<&str>) -> Option<PathBuf> {
+        self.executables
+            .iter()
+            .find(|e| package.is_some() && e.file_stem().and_then(|s| s.to_str()) == package)
+            .or_else(|| self.executables.first())
+            .cloned()
+    }
+}
+
+/// `path` without symlinks or `..`, as far as it exists
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Read the package name from the project's Cargo.toml
+fn package_name(root: &Path) -> Option<String> {
+    let manifest = std::fs::read_to_string(root.join("Cargo.toml")).ok()?;
+    let manifest: toml::Value = toml::from_str(&manifest).ok()?;
+    manifest
+        .get("package")?
+        .get("name")?
+        .as_str()
+        .map(String::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+
+    #[test]
+    fn messages_work() {
+        let mut messages = Messages::new(Path::new("/work/app/Cargo.toml"));
+        let artifact = |manifest: &str, executable: &str| {
+            serde_json::json!({
+                "reason": "compiler-artifact",
+                "manifest_path": manifest,
+                "executable": executable,
+            })
+            .to_string()
+        };
+        let lines = [
+            "build script output".to_string(),
+            r#"{"reason":"compiler-message","message":{"rendered":"warning: unused\n"}}"#
+                .to_string(),
+            artifact("/work/dep/Cargo.toml", "/work/target/debug/dep"),
+            artifact("/work/app/Cargo.toml", "/work/target/debug/helper"),
+            artifact("/work/app/Cargo.toml", "/work/target/debug/app"),
+            r#"{"reason":"compiler-artifact","manifest_path":"/work/app/Cargo.toml","executable":null}"#
+                .to_string(),
+        ];
+        let shown: Vec<String> = lines
+            .iter()
+            .filter_map(|line| messages.read(line))
+            .collect();
+        assert_eq!(shown, ["warning: unused\n"]);
+        assert!(!messages.success);
+        assert_eq!(
+            messages.executable(Some("app")),
+            Some(PathBuf::from("/work/target/debug/app"))
+        );
+        assert_eq!(
+            messages.executable(None),
+            Some(PathBuf::from("/work/target/debug/helper"))
+        );
+
+        messages.read(r#"{"reason":"build-finished","success":true}"#);
+        assert!(messages.success);
+    }
+
+    #[test]
+    fn package_name_works() {
+        let dir = scratch("cargo-package");
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(package_name(&dir), None);
+        std::fs::write(dir.join("Cargo.toml"), "[package]\nname = \"app\"\n").unwrap();
+        assert_eq!(package_name(&dir).as_deref(), Some("app"));
+    }
+}
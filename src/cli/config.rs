@@ -1,17 +1,63 @@
 //! Config for the command line tool
 //!
+//! The global config file keeps leap's own state, such as the latest project
+//! template, as well as settings. Settings are merged from, lowest first:
+//!
+//! 1. the defaults below
+//! 2. the global config file, or the one given with `--config`
+//! 3. `leap.toml` in the current directory or the nearest parent with one
+//! 4. `LEAP_*` environment variables, e.g. `LEAP_SERVE_PORT` for `serve.port`
+//! 5. command line flags, applied by the sub-commands
+//!
+//! Only the [`SETTINGS`] can be set in the last three, everything else
+//! belongs to the global file.
+//!
+//! The global file records the [`SCHEMA_VERSION`] it was written with. Files
+//! from older versions of leap are migrated when they are read, after being
+//! copied to `cli.toml.v<version>.bak`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
 use directories::ProjectDirs;
 use semver::Version;
 use serde::{Deserialize, Serialize};
 
+use crate::cli::error::Error;
+use crate::cli::github;
+
+/// The project settings file
+pub const PROJECT_FILE: &str = "leap.toml";
+
+/// Sections and keys which can be set for a project or in the environment.
+/// `github` is left out so a project cannot send the token to another server,
+/// or have it committed to its repository.
+pub const SETTINGS: [&str; 3] = ["new", "serve", "update.refresh"];
+
+/// Upgrades from each schema version to the next, the first upgrades version 1
+const MIGRATIONS: [fn(&mut toml::value::Table); 1] = [describe_template];
+
+/// The version of the config file's layout, raised with each migration
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+/// The config file given with `--config`
+static PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Read and write the config at `path` instead of the one in the config directory
+pub fn set_path(path: PathBuf) {
+    let _ = PATH.set(path);
+}
+
 /// Get directory paths for cli
 pub fn dir() -> Option<ProjectDirs> {
     directories::ProjectDirs::from("rs", "leap", "cli")
 }
 
 /// Get cli config or create a new config
-pub fn config() -> Result<Config, std::io::Error> {
+pub fn config() -> Result<Config, Error> {
     if !exists() {
         return new();
     }
@@ -20,17 +66,14 @@ pub fn config() -> Result<Config, std::io::Error> {
 
 /// Does config exist?
 fn exists() -> bool {
-    if dir().is_none() {
-        return false;
-    }
-
-    dir().unwrap().config_dir().exists()
+    path().is_ok_and(|path| path.exists())
 }
 
 /// Generate a new config for the cli
-fn new() -> Result<Config, std::io::Error> {
-    if !exists() {
-        std::fs::create_dir_all(dir().unwrap().config_dir())?;
+fn new() -> Result<Config, Error> {
+    let path = path()?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
     }
 
     match write(Config::default()) {
@@ -39,54 +82,477 @@ fn new() -> Result<Config, std::io::Error> {
     }
 }
 
+/// Where the config file is
+pub fn path() -> Result<PathBuf, Error> {
+    if let Some(path) = PATH.get() {
+        return Ok(path.clone());
+    }
+    match dir() {
+        Some(dir) => Ok(dir.config_dir().join("cli.toml")),
+        None => Err(Error::Config("cannot find config path".to_string())),
+    }
+}
+
 /// Read the config file
-pub fn read() -> Result<Config, std::io::Error> {
+pub fn read() -> Result<Config, Error> {
     log::debug!("read config");
     if !exists() {
-        return Err(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Cannot find config path".to_string(),
-        ));
+        return Err(Error::Config("cannot find config path".to_string()));
+    }
+
+    let path = path()?;
+    let toml_string = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+    let (value, from) = parse(&path, &toml_string)?;
+    let deserialize: Result<Config, toml::de::Error> = value.try_into();
+    let config = match deserialize {
+        Ok(config) => config,
+        Err(e) => return Err(Error::Config(format!("{}: {}", path.display(), e))),
+    };
+
+    if from < SCHEMA_VERSION {
+        log::info!(
+            "migrating {} from schema version {} to {}",
+            path.display(),
+            from,
+            SCHEMA_VERSION
+        );
+        backup(&path, from)?;
+        write(config.clone())?;
+    }
+    Ok(config)
+}
+
+/// Parse a config file, migrating it to the current schema.
+///
+/// Also returns the schema version the file was written with.
+fn parse(path: &Path, text: &str) -> Result<(toml::Value, u32), Error> {
+    let invalid = |message: String| Error::Config(format!("{}: {}", path.display(), message));
+    let mut value: toml::Value = toml::from_str(text).map_err(|e| invalid(e.to_string()))?;
+    let table = value
+        .as_table_mut()
+        .ok_or_else(|| invalid("is not a table".to_string()))?;
+
+    // Files from before the schema was versioned are version 1
+    let from = match table.get("schema_version") {
+        None => 1,
+        Some(version) => version
+            .as_integer()
+            .and_then(|version| u32::try_from(version).ok())
+            .filter(|version| *version >= 1)
+            .ok_or_else(|| invalid(format!("schema_version {} is not valid", version)))?,
+    };
+    if from > SCHEMA_VERSION {
+        return Err(invalid(format!(
+            "schema version {} is newer than this version of leap knows about ({}), please update leap",
+            from, SCHEMA_VERSION
+        )));
     }
 
-    let toml_string = std::fs::read_to_string(dir().unwrap().config_dir().join("cli.toml"))?;
-    let deserialize: Result<Config, toml::de::Error> = toml::from_str(toml_string.as_str());
-    match deserialize {
-        Ok(config) => Ok(config),
-        Err(e) => Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e.to_string(),
-        )),
+    for migration in &MIGRATIONS[from as usize - 1..] {
+        migration(table);
     }
+    table.insert(
+        "schema_version".to_string(),
+        toml::Value::Integer(SCHEMA_VERSION.into()),
+    );
+    Ok((value, from))
+}
+
+/// Copy the config file aside before migrating it
+fn backup(path: &Path, version: u32) -> Result<PathBuf, Error> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".v{}.bak", version));
+    let backup = path.with_file_name(name);
+    std::fs::copy(path, &backup).map_err(|e| Error::io(&backup, e))?;
+    Ok(backup)
+}
+
+/// A migration of the config file
+#[derive(Debug, Clone)]
+pub struct Migration {
+    /// The schema version the file was written with
+    pub from: u32,
+    /// The keys which are added or changed
+    pub changes: Vec<(String, toml::Value)>,
+    /// Where the file was copied to before it was migrated
+    pub backup: Option<PathBuf>,
+}
+
+/// Migrate the config file to the current schema, unless this is a dry run
+pub fn migrate(dry_run: bool) -> Result<Migration, Error> {
+    let path = path()?;
+    if !exists() {
+        return Ok(Migration {
+            from: SCHEMA_VERSION,
+            changes: Vec::new(),
+            backup: None,
+        });
+    }
+
+    let text = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+    let before = toml::from_str(&text)
+        .map(|value| flatten(&value))
+        .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+    let (value, from) = parse(&path, &text)?;
+    let config: Config = value
+        .try_into()
+        .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+    let changes = list(&config)?
+        .into_iter()
+        .filter(|change| !before.contains(change))
+        .collect();
+
+    let mut migration = Migration {
+        from,
+        changes,
+        backup: None,
+    };
+    if from < SCHEMA_VERSION && !dry_run {
+        migration.backup = Some(backup(&path, from)?);
+        write(config)?;
+    }
+    Ok(migration)
+}
+
+/// Version 2 records where the cached template came from, its tag and commit
+fn describe_template(config: &mut toml::value::Table) {
+    let template = match config
+        .get_mut("template")
+        .and_then(toml::Value::as_table_mut)
+    {
+        Some(template) => template,
+        None => return,
+    };
+    let tag = template
+        .get("version")
+        .and_then(toml::Value::as_str)
+        .map(|version| toml::Value::String(format!("v{}", version)));
+    // Version 1 recorded the archive's name, `template-<sha>.zip`, as its
+    // hash. Without a commit upgrades look the template up by its version.
+    let sha = ["hash", "compressed"]
+        .iter()
+        .filter_map(|key| template.get(*key).and_then(toml::Value::as_str))
+        .find_map(short_sha)
+        .unwrap_or_default();
+
+    template
+        .entry("source".to_string())
+        .or_insert_with(|| github::PROJECT_TEMPLATE_REPOSITORY.into());
+    if let Some(tag) = tag {
+        template.entry("tag".to_string()).or_insert(tag);
+    }
+    template.entry("sha".to_string()).or_insert(sha.into());
+}
+
+/// The commit in a version 1 template hash, either the short sha itself or
+/// the name of the archive downloaded for it
+fn short_sha(hash: &str) -> Option<String> {
+    let sha = hash
+        .strip_prefix("template-")
+        .and_then(|name| name.strip_suffix(".zip"))
+        .unwrap_or(hash);
+    let hex = !sha.is_empty() && sha.chars().all(|c| c.is_ascii_hexdigit());
+    hex.then(|| sha.to_lowercase())
 }
 
 /// Update / write config file
-pub fn write(config: Config) -> Result<(), std::io::Error> {
+pub fn write(config: Config) -> Result<(), Error> {
     let serialize: Result<String, toml::ser::Error> = toml::to_string(&config);
     match serialize {
         Ok(toml_string) => {
-            match std::fs::write(dir().unwrap().config_dir().join("cli.toml"), toml_string) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    e.to_string(),
-                )),
-            }
+            let path = path()?;
+            std::fs::write(&path, toml_string).map_err(|e| Error::io(&path, e))
         }
-        Err(e) => Err(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            e.to_string(),
-        )),
+        Err(e) => Err(Error::Config(e.to_string())),
     }
 }
 
 #[allow(dead_code)]
-pub fn delete() -> Result<(), std::io::Error> {
-    let p = dir().unwrap().config_dir().join("cli.toml");
-    std::fs::remove_file(p)
+pub fn delete() -> Result<(), Error> {
+    let p = path()?;
+    std::fs::remove_file(&p).map_err(|e| Error::io(&p, e))
+}
+
+/// The config sub-commands
+#[derive(Debug, Clone)]
+pub enum ConfigCommand {
+    /// Print the value of a dotted key such as `template.version`
+    Get { key: String },
+    /// Change the value of a key
+    Set { key: String, value: String },
+    /// Put a key back to its default value
+    Unset { key: String },
+    /// Print every key and its value, and where the value came from
+    List { show_origin: bool },
+    /// Print where the config file is
+    Path,
+    /// Open the config file in `$EDITOR`
+    Edit,
+    /// Migrate the config file to the current schema
+    Migrate { dry_run: bool },
+}
+
+/// The value of a dotted key such as `template.version`
+pub fn get(config: &Config, key: &str) -> Result<toml::Value, Error> {
+    let value = to_value(config)?;
+    lookup(&value, key).cloned().ok_or_else(|| unknown(key))
+}
+
+/// Change the value of a dotted key, parsing `value` as the type the key holds
+pub fn set(config: &Config, key: &str, value: &str) -> Result<Config, Error> {
+    let mut root = to_value(config)?;
+    let current = lookup_mut(&mut root, key).ok_or_else(|| unknown(key))?;
+    let invalid =
+        |expected: &str| Error::Usage(format!("{} must be {}, not '{}'", key, expected, value));
+    *current = match current {
+        toml::Value::Integer(_) => value
+            .parse()
+            .map(toml::Value::Integer)
+            .map_err(|_| invalid("a whole number"))?,
+        toml::Value::Boolean(_) => value
+            .parse()
+            .map(toml::Value::Boolean)
+            .map_err(|_| invalid("true or false"))?,
+        toml::Value::String(_) => toml::Value::String(value.to_string()),
+        _ => {
+            return Err(Error::Usage(format!(
+                "{} is a section, set one of its keys",
+                key
+            )))
+        }
+    };
+    checked(root, key)
+}
+
+/// Put a dotted key back to its default value
+pub fn unset(config: &Config, key: &str) -> Result<Config, Error> {
+    let default = get(&Config::default(), key)?;
+    let mut root = to_value(config)?;
+    *lookup_mut(&mut root, key).ok_or_else(|| unknown(key))? = default;
+    checked(root, key)
+}
+
+/// Every dotted key and its value
+pub fn list(config: &Config) -> Result<Vec<(String, toml::Value)>, Error> {
+    Ok(flatten(&to_value(config)?))
+}
+
+/// Where a setting came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    /// The global config file
+    Global(PathBuf),
+    /// A project's `leap.toml`
+    Project(PathBuf),
+    /// A `LEAP_*` environment variable
+    Env(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::Global(path) => write!(f, "global:{}", path.display()),
+            Origin::Project(path) => write!(f, "project:{}", path.display()),
+            Origin::Env(name) => write!(f, "env:{}", name),
+        }
+    }
+}
+
+/// The config merged from every layer
+#[derive(Debug, Clone)]
+pub struct Layered {
+    pub config: Config,
+    /// Where each dotted key's value came from
+    pub origins: BTreeMap<String, Origin>,
+}
+
+/// The config merged from the global file, the project and the environment
+pub fn settings() -> Result<Config, Error> {
+    layered().map(|layered| layered.config)
+}
+
+/// The merged config, with where each value came from
+pub fn layered() -> Result<Layered, Error> {
+    let project = match project_file(&std::env::current_dir()?) {
+        Some(path) => {
+            let text = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+            let value = toml::from_str(&text)
+                .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+            Some((path, value))
+        }
+        None => None,
+    };
+    layer(&config()?, &path()?, project, &std::env::vars().collect())
+}
+
+/// The nearest `leap.toml` in `start` or its parents
+fn project_file(start: &Path) -> Option<PathBuf> {
+    start
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+/// The environment variable setting a dotted key, e.g. `LEAP_SERVE_PORT`
+pub fn env_name(key: &str) -> String {
+    format!("LEAP_{}", key.to_uppercase().replace('.', "_"))
+}
+
+/// Can the key be set for a project or in the environment?
+fn setting(key: &str) -> bool {
+    SETTINGS.iter().any(|setting| {
+        key.strip_prefix(setting)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// Merge a project's settings and the environment into the global config
+fn layer(
+    global: &Config,
+    global_path: &Path,
+    project: Option<(PathBuf, toml::Value)>,
+    env: &HashMap<String, String>,
+) -> Result<Layered, Error> {
+    let defaults = list(&Config::default())?;
+    let mut value = to_value(global)?;
+    let mut origins: BTreeMap<String, Origin> = defaults
+        .iter()
+        .map(|(key, default)| match lookup(&value, key) {
+            Some(global) if global != default => (key.clone(), Origin::Global(global_path.into())),
+            _ => (key.clone(), Origin::Default),
+        })
+        .collect();
+
+    if let Some((path, project)) = project {
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        for (key, mut setting_value) in flatten(&project) {
+            // Templates on disk are relative to the project
+            let local = setting_value.as_str().map(|source| dir.join(source));
+            if let (true, Some(local)) = (key == "new.template", local) {
+                if local.exists() {
+                    setting_value = toml::Value::String(local.display().to_string());
+                }
+            }
+            match lookup_mut(&mut value, &key) {
+                Some(current) if setting(&key) => {
+                    *current = setting_value;
+                    origins.insert(key, Origin::Project(path.clone()));
+                }
+                Some(_) if key == "github.token" => log::warn!(
+                    "{}: github.token is ignored, keep it out of projects where it may be \
+                     committed and set it with leap config set",
+                    path.display()
+                ),
+                Some(_) => log::warn!("{}: {} can only be set globally", path.display(), key),
+                None => log::warn!("{}: {} is not a config key", path.display(), key),
+            }
+        }
+        from_value(value.clone())
+            .map_err(|e| Error::Config(format!("{}: {}", path.display(), e)))?;
+    }
+
+    let mut config: Config = value.try_into().map_err(|e| Error::Config(e.to_string()))?;
+    for (key, _) in defaults.iter().filter(|(key, _)| setting(key)) {
+        let name = env_name(key);
+        if let Some(text) = env.get(&name) {
+            config =
+                set(&config, key, text).map_err(|e| Error::Config(format!("{}: {}", name, e)))?;
+            origins.insert(key.clone(), Origin::Env(name));
+        }
+    }
+
+    Ok(Layered { config, origins })
+}
+
+/// Open the config file in the user's editor, then check it still reads
+pub fn edit() -> Result<(), Error> {
+    config()?;
+    let path = path()?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+
+    // The editor may come with arguments, e.g. `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words
+        .next()
+        .ok_or_else(|| Error::Config("$EDITOR is empty".to_string()))?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .map_err(|e| Error::Config(format!("unable to run {} because {}", editor, e)))?;
+    if !status.success() {
+        return Err(Error::Config(format!("{} exited with {}", editor, status)));
+    }
+    read().map(|_| ())
+}
+
+fn flatten(value: &toml::Value) -> Vec<(String, toml::Value)> {
+    fn walk(prefix: &str, value: &toml::Value, keys: &mut Vec<(String, toml::Value)>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (name, value) in table {
+                    let key = if prefix.is_empty() {
+                        name.clone()
+                    } else {
+                        format!("{}.{}", prefix, name)
+                    };
+                    walk(&key, value, keys);
+                }
+            }
+            value => keys.push((prefix.to_string(), value.clone())),
+        }
+    }
+
+    let mut keys = Vec::new();
+    walk("", value, &mut keys);
+    keys
+}
+
+fn to_value(config: &Config) -> Result<toml::Value, Error> {
+    toml::Value::try_from(config).map_err(|e| Error::Config(e.to_string()))
+}
+
+/// Turn a changed value back into a config, which checks the values
+fn from_value(value: toml::Value) -> Result<Config, String> {
+    let config: Config = value.try_into().map_err(|e| e.to_string())?;
+    if config.schema_version != SCHEMA_VERSION {
+        return Err("schema_version is kept by leap, see leap config migrate".to_string());
+    }
+    let api_url = &config.github.api_url;
+    if !api_url.starts_with("http://") && !api_url.starts_with("https://") {
+        return Err(format!(
+            "github.api_url must be an http:// or https:// URL, not '{}'",
+            api_url
+        ));
+    }
+    Ok(config)
+}
+
+/// Check a value changed with `leap config`
+fn checked(value: toml::Value, key: &str) -> Result<Config, Error> {
+    from_value(value).map_err(|e| Error::Usage(format!("invalid value for {}, {}", key, e)))
+}
+
+fn lookup<'a>(value: &'a toml::Value, key: &str) -> Option<&'a toml::Value> {
+    key.split('.')
+        .try_fold(value, |value, name| value.get(name))
+}
+
+fn lookup_mut<'a>(value: &'a mut toml::Value, key: &str) -> Option<&'a mut toml::Value> {
+    key.split('.')
+        .try_fold(value, |value, name| value.get_mut(name))
+}
+
+fn unknown(key: &str) -> Error {
+    Error::Usage(format!("{} is not a config key, see leap config list", key))
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct PackageConfig {
     pub name: String,
     pub version: Version,
@@ -102,11 +568,18 @@ impl Default for PackageConfig {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct TemplateConfig {
     pub hash: String,
     pub version: Version,
     pub compressed: String,
     pub extracted: String,
+    /// Where the template came from, a repository, URL or path
+    pub source: String,
+    /// The tag the template was released as
+    pub tag: String,
+    /// The commit the tag points at
+    pub sha: String,
 }
 
 impl Default for TemplateConfig {
@@ -116,60 +589,282 @@ impl Default for TemplateConfig {
             version: Version::parse("0.2.0").unwrap(),
             compressed: "template-6cdba5e.zip".to_string(),
             extracted: "daniel-samson-leap-project-template-6cdba5e".to_string(),
+            source: github::PROJECT_TEMPLATE_REPOSITORY.to_string(),
+            tag: "v0.2.0".to_string(),
+            sha: "6cdba5e".to_string(),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct UpdateConfig {
+    /// When the project template was last checked for a newer release
     pub last: usize,
+    /// How often to check
+    pub refresh: Refresh,
 }
 
 impl Default for UpdateConfig {
     fn default() -> Self {
-        UpdateConfig { last: 1 }
+        UpdateConfig {
+            last: 1,
+            refresh: Refresh::Every(180 * DAY),
+        }
+    }
+}
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+const WEEK: u64 = 7 * DAY;
+/// Units of a refresh interval, largest first
+const UNITS: [(&str, u64); 5] = [
+    ("w", WEEK),
+    ("d", DAY),
+    ("h", HOUR),
+    ("m", MINUTE),
+    ("s", 1),
+];
+
+/// How often to check for a newer release of the project template, written
+/// as `always`, `never` or an interval such as `12h`, `30d` or `2w`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Refresh {
+    /// Every time a project is generated
+    Always,
+    /// Only when no template is cached
+    Never,
+    /// When the last check is older than this many seconds
+    Every(u64),
+}
+
+impl Refresh {
+    /// Is a check due at `now`, when the last one was at `last`?
+    pub fn due(self, last: u64, now: u64) -> bool {
+        match self {
+            Refresh::Always => true,
+            Refresh::Never => false,
+            Refresh::Every(interval) => now.saturating_sub(last) >= interval,
+        }
+    }
+}
+
+impl fmt::Display for Refresh {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refresh::Always => write!(f, "always"),
+            Refresh::Never => write!(f, "never"),
+            Refresh::Every(seconds) => {
+                let (unit, size) = UNITS
+                    .iter()
+                    .find(|(_, size)| seconds % size == 0)
+                    .unwrap_or(&("s", 1));
+                write!(f, "{}{}", seconds / size, unit)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for Refresh {
+    type Error = String;
+
+    fn try_from(refresh: String) -> Result<Self, Self::Error> {
+        let invalid = || {
+            format!(
+                "'{}' is not always, never or an interval such as 12h, 30d or 2w",
+                refresh
+            )
+        };
+        match refresh.as_str() {
+            "always" => return Ok(Refresh::Always),
+            "never" => return Ok(Refresh::Never),
+            _ => {}
+        }
+        let split = refresh
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(refresh.len());
+        let (count, unit) = refresh.split_at(split);
+        let count: u64 = count.parse().map_err(|_| invalid())?;
+        let size = match unit {
+            "" => 1,
+            unit => {
+                UNITS
+                    .iter()
+                    .find(|(name, _)| *name == unit)
+                    .ok_or_else(invalid)?
+                    .1
+            }
+        };
+        count
+            .checked_mul(size)
+            .map(Refresh::Every)
+            .ok_or_else(invalid)
+    }
+}
+
+impl From<Refresh> for String {
+    fn from(refresh: Refresh) -> String {
+        refresh.to_string()
     }
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+/// Where templates are released, only in the global config so a project
+/// cannot point the token at another server
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct GithubConfig {
+    /// Base URL of the GitHub compatible API templates are released on
+    pub api_url: String,
+    /// A token to authenticate to the API with, `GITHUB_TOKEN` is used for
+    /// api.github.com when this is empty
+    pub token: String,
+}
+
+impl Default for GithubConfig {
+    fn default() -> Self {
+        GithubConfig {
+            api_url: github::API_URL.to_string(),
+            token: String::new(),
+        }
+    }
+}
+
+/// Settings for `leap new`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct NewConfig {
+    /// The template projects are generated from, see `leap new --template`
+    pub template: String,
+}
+
+impl Default for NewConfig {
+    fn default() -> Self {
+        NewConfig {
+            template: github::PROJECT_TEMPLATE_REPOSITORY.to_string(),
+        }
+    }
+}
+
+/// How downloaded templates are checked, only in the global config so a
+/// project cannot turn it off
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct VerifyConfig {
+    /// The minisign public key templates must be signed with, when set
+    pub public_key: String,
+}
+
+/// How much a template archive may hold, only in the global config
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ExtractConfig {
+    /// The most files and directories in an archive
+    pub max_entries: usize,
+    /// The most bytes an archive may hold once extracted
+    pub max_size: u64,
+}
+
+impl Default for ExtractConfig {
+    fn default() -> Self {
+        ExtractConfig {
+            max_entries: 10_000,
+            max_size: 512 * 1024 * 1024,
+        }
+    }
+}
+
+/// Settings for `leap serve` and `leap watch`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct ServeConfig {
+    pub host: String,
+    pub port: u16,
+    pub environment: String,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            environment: "development".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct Config {
+    /// The layout of the config file, see [`SCHEMA_VERSION`]
+    pub schema_version: u32,
     pub package: PackageConfig,
     pub template: TemplateConfig,
     pub update: UpdateConfig,
+    pub github: GithubConfig,
+    pub new: NewConfig,
+    pub serve: ServeConfig,
+    pub verify: VerifyConfig,
+    pub extract: ExtractConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            schema_version: SCHEMA_VERSION,
+            package: PackageConfig::default(),
+            template: TemplateConfig::default(),
+            update: UpdateConfig::default(),
+            github: GithubConfig::default(),
+            new: NewConfig::default(),
+            serve: ServeConfig::default(),
+            verify: VerifyConfig::default(),
+            extract: ExtractConfig::default(),
+        }
+    }
 }
 
 impl Config {
     pub fn with_package(&self, package: PackageConfig) -> Config {
         Config {
+            schema_version: self.schema_version,
             package,
             template: self.template.clone(),
             update: self.update.clone(),
+            github: self.github.clone(),
+            new: self.new.clone(),
+            serve: self.serve.clone(),
+            verify: self.verify.clone(),
+            extract: self.extract.clone(),
         }
     }
 
     pub fn with_template(&self, template: TemplateConfig) -> Config {
         Config {
+            schema_version: self.schema_version,
             package: self.package.clone(),
             template,
             update: self.update.clone(),
+            github: self.github.clone(),
+            new: self.new.clone(),
+            serve: self.serve.clone(),
+            verify: self.verify.clone(),
+            extract: self.extract.clone(),
         }
     }
 
     pub fn with_update(&self, update: UpdateConfig) -> Config {
         Config {
+            schema_version: self.schema_version,
             package: self.package.clone(),
             template: self.template.clone(),
             update,
-        }
-    }
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            package: PackageConfig::default(),
-            template: TemplateConfig::default(),
-            update: UpdateConfig::default(),
+            github: self.github.clone(),
+            new: self.new.clone(),
+            serve: self.serve.clone(),
+            verify: self.verify.clone(),
+            extract: self.extract.clone(),
         }
     }
 }
@@ -189,4 +884,157 @@ mod tests {
             new_config.unwrap().template.hash
         );
     }
+
+    #[test]
+    fn keys_work() {
+        let config = Config::default();
+        assert_eq!(
+            get(&config, "template.version").unwrap().as_str(),
+            Some("0.2.0")
+        );
+        assert!(get(&config, "template.nope").is_err());
+        assert!(list(&config)
+            .unwrap()
+            .iter()
+            .any(|(key, _)| key == "github.api_url"));
+
+        let config = set(&config, "template.version", "0.3.1").unwrap();
+        assert_eq!(config.template.version.to_string(), "0.3.1");
+        assert!(set(&config, "template.version", "latest").is_err());
+        let config = set(&config, "update.last", "42").unwrap();
+        assert_eq!(config.update.last, 42);
+        assert!(set(&config, "update.last", "yesterday").is_err());
+        assert!(set(&config, "template", "x").is_err());
+        assert!(set(&config, "github.api_url", "api.github.com").is_err());
+
+        let config = unset(&config, "template.version").unwrap();
+        assert_eq!(config.template.version.to_string(), "0.2.0");
+    }
+
+    #[test]
+    fn migrations_work() {
+        let path = Path::new("cli.toml");
+        let old = "[package]\nname = \"leap\"\nversion = \"0.2.0\"\n\n\
+                   [template]\nhash = \"template-1a2b3c4.zip\"\nversion = \"0.3.0\"\n\
+                   compressed = \"template-1a2b3c4.zip\"\n\
+                   extracted = \"daniel-samson-leap-project-template-1a2b3c4\"\n\n\
+                   [update]\nlast = 1600000000\n";
+        let (value, from) = parse(path, old).unwrap();
+        assert_eq!(from, 1);
+        let config: Config = value.try_into().unwrap();
+        assert_eq!(config.schema_version, SCHEMA_VERSION);
+        assert_eq!(config.template.source, github::PROJECT_TEMPLATE_REPOSITORY);
+        assert_eq!(config.template.tag, "v0.3.0");
+        assert_eq!(config.template.sha, "1a2b3c4");
+        assert_eq!(config.update.last, 1_600_000_000);
+        assert_eq!(config.github.api_url, github::API_URL);
+
+        // As written by leap 0.2.0 before it had fetched a template
+        let default = "[package]\nname = \"leap\"\nversion = \"0.2.0\"\n\n\
+                       [template]\nhash = \"6cdba5e\"\nversion = \"0.2.0\"\n\
+                       compressed = \"template-6cdba5e.zip\"\n\
+                       extracted = \"daniel-samson-leap-project-template-6cdba5e\"\n\n\
+                       [update]\nlast = 0\n";
+        let config: Config = parse(path, default).unwrap().0.try_into().unwrap();
+        assert_eq!(config.template.sha, "6cdba5e");
+        let unknown = "[template]\nhash = \"latest.zip\"\nversion = \"0.3.0\"\n";
+        let config: Config = parse(path, unknown).unwrap().0.try_into().unwrap();
+        assert_eq!(config.template.sha, "");
+        assert_eq!(short_sha("template-.zip"), None);
+        assert_eq!(short_sha("latest.zip"), None);
+
+        let current = toml::to_string(&Config::default()).unwrap();
+        assert_eq!(parse(path, &current).unwrap().1, SCHEMA_VERSION);
+        assert!(parse(path, "schema_version = 99").is_err());
+        assert!(parse(path, "schema_version = 0").is_err());
+        assert!(set(&Config::default(), "schema_version", "1").is_err());
+    }
+
+    #[test]
+    fn refresh_works() {
+        let parse = |refresh: &str| Refresh::try_from(refresh.to_string());
+        assert_eq!(parse("always"), Ok(Refresh::Always));
+        assert_eq!(parse("never"), Ok(Refresh::Never));
+        assert_eq!(parse("12h"), Ok(Refresh::Every(12 * HOUR)));
+        assert_eq!(parse("90"), Ok(Refresh::Every(90)));
+        assert!(parse("soon").is_err());
+        assert!(parse("3y").is_err());
+        assert!(parse("h").is_err());
+        assert_eq!(Refresh::Every(14 * DAY).to_string(), "2w");
+        assert_eq!(Refresh::Every(90).to_string(), "90s");
+
+        let day = Refresh::Every(DAY);
+        assert!(!day.due(1_000_000, 1_000_000 + DAY - 1));
+        assert!(day.due(1_000_000, 1_000_000 + DAY));
+        // A clock which went backwards is not a reason to check
+        assert!(!day.due(1_000_000, 0));
+        assert!(Refresh::Always.due(1_000_000, 1_000_000));
+        assert!(!Refresh::Never.due(0, u64::MAX));
+
+        let config = set(&Config::default(), "update.refresh", "never").unwrap();
+        assert_eq!(config.update.refresh, Refresh::Never);
+        assert!(set(&config, "update.refresh", "sometimes").is_err());
+    }
+
+    #[test]
+    fn layers_work() {
+        let global = set(&Config::default(), "serve.host", "0.0.0.0").unwrap();
+        let global_path = Path::new("/home/cli.toml");
+        let project_path = PathBuf::from("/project/leap.toml");
+        let project: toml::Value = toml::from_str(
+            "[serve]\nport = 3000\nenvironment = \"staging\"\n[template]\nversion = \"9.9.9\"",
+        )
+        .unwrap();
+        let env: HashMap<String, String> = vec![
+            ("LEAP_SERVE_ENVIRONMENT".to_string(), "test".to_string()),
+            ("LEAP_TEMPLATE_VERSION".to_string(), "8.8.8".to_string()),
+        ]
+        .into_iter()
+        .collect();
+
+        let layered = layer(
+            &global,
+            global_path,
+            Some((project_path.clone(), project)),
+            &env,
+        )
+        .unwrap();
+        assert_eq!(layered.config.serve.host, "0.0.0.0");
+        assert_eq!(layered.config.serve.port, 3000);
+        assert_eq!(layered.config.serve.environment, "test");
+        assert_eq!(layered.config.template.version.to_string(), "0.2.0");
+        assert_eq!(
+            layered.origins["serve.host"],
+            Origin::Global(global_path.into())
+        );
+        assert_eq!(
+            layered.origins["serve.port"],
+            Origin::Project(project_path.clone())
+        );
+        assert_eq!(
+            layered.origins["serve.environment"],
+            Origin::Env("LEAP_SERVE_ENVIRONMENT".to_string())
+        );
+        assert_eq!(layered.origins["template.version"], Origin::Default);
+
+        // The token is only ever sent to the API the global config trusts
+        let project: toml::Value =
+            toml::from_str("[github]\napi_url = \"https://evil.test\"\ntoken = \"stolen\"")
+                .unwrap();
+        let env = vec![(
+            "LEAP_GITHUB_API_URL".to_string(),
+            "https://evil.test".to_string(),
+        )]
+        .into_iter()
+        .collect();
+        let layered = layer(&global, global_path, Some((project_path, project)), &env).unwrap();
+        assert_eq!(layered.config.github.api_url, github::API_URL);
+        assert!(layered.config.github.token.is_empty());
+        assert_eq!(layered.origins["github.api_url"], Origin::Default);
+
+        let env = vec![("LEAP_SERVE_PORT".to_string(), "http".to_string())]
+            .into_iter()
+            .collect();
+        assert!(layer(&global, global_path, None, &env).is_err());
+    }
 }
@@ -1,26 +1,520 @@
-use async_std::task;
-
-/// Make a GET request
-pub fn get(url: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-    task::block_on(async {
-        let response = surf::get(url)
-            .set_header("Accept", "*/*")
-            .set_header("User-Agent", "https://leap.rs/")
-            .await;
-        match response {
-            Ok(mut res) => {
-                if res.status() == 302 {
-                    let redirected_response = surf::get(res.header("Location").unwrap())
-                        .set_header("Accept", "*/*")
-                        .set_header("User-Agent", "https://leap.rs/")
-                        .recv_bytes()
-                        .await?;
-                    return Ok(redirected_response);
+//! Downloads
+//!
+//! Redirects are followed, up to [`REDIRECTS`] of them. Failures which may
+//! not happen again, the network going away or a busy server, are retried
+//! with a growing wait in between. Files are downloaded next to where they
+//! go as `<name>.partial`, and renamed into place once complete. The next
+//! attempt carries on from a partial download with a `Range` request, made
+//! conditional with `If-Range` on the `ETag` or `Last-Modified` the server
+//! sent for it, kept in `<name>.partial.validator`. A partial download the
+//! server cannot vouch for is started again. A progress bar is drawn on
+//! stderr when it is a terminal.
+
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::cli::error::Error;
+use crate::cli::http::{Body, Header, HttpClient, Response};
+use crate::cli::{cache, output};
+
+/// How many redirects are followed before giving up
+const REDIRECTS: usize = 10;
+
+/// How many times a failed download is tried again
+const RETRIES: u32 = 3;
+
+/// How long to wait before trying again, doubled after each attempt
+const BACKOFF: Duration = Duration::from_millis(500);
+
+/// How often the progress bar is redrawn
+const REDRAW: Duration = Duration::from_millis(100);
+
+/// Download `url` into memory, for small files such as checksums
+pub fn get(client: &dyn HttpClient, url: &str) -> Result<Vec<u8>, Error> {
+    retry(|| {
+        let mut body = Vec::new();
+        let response = follow(client, url, &[("Accept", "*/*".to_string())], &mut body)?;
+        check(url, &response)?;
+        Ok(body)
+    })
+}
+
+/// Download `url` into memory if it is there, for files which may not be published
+pub fn find(client: &dyn HttpClient, url: &str) -> Result<Option<Vec<u8>>, Error> {
+    retry(|| {
+        let mut body = Vec::new();
+        let response = follow(client, url, &[("Accept", "*/*".to_string())], &mut body)?;
+        if matches!(response.status, 404 | 410) {
+            return Ok(None);
+        }
+        check(url, &response)?;
+        Ok(Some(body))
+    })
+}
+
+/// Download `url` to `path`, carrying on from an earlier attempt if there was one
+pub fn to_file(client: &dyn HttpClient, url: &str, path: &Path) -> Result<(), Error> {
+    let partial = partial(path);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    }
+
+    let downloaded = retry(|| {
+        let mut file = Partial::open(&partial)?;
+        let mut headers = vec![("Accept", "*/*".to_string())];
+        if file.written > 0 {
+            match std::fs::read_to_string(&file.validator) {
+                Ok(validator) => {
+                    log::info!("resuming {} from {} bytes", url, file.written);
+                    headers.push(("Range", format!("bytes={}-", file.written)));
+                    headers.push(("If-Range", validator.trim().to_string()));
                 }
+                // Nothing says the file has not changed since
+                Err(_) => file.restart()?,
+            }
+        }
+        let response = follow(client, url, &headers, &mut file)?;
+        if response.status == 416 || file.mismatched {
+            // What was downloaded before is not part of this file, or the
+            // server sent some other part of it
+            file.restart()?;
+            return Err(Error::Network {
+                url: url.to_string(),
+                message: "the partial download does not match, starting again".to_string(),
+            });
+        }
+        check(url, &response)?;
+        file.finish(url)
+    });
+    let validator = validator(&partial);
+    if let Err(e) = downloaded {
+        // Keep what arrived for the next attempt, unless nothing did
+        if std::fs::metadata(&partial).is_ok_and(|metadata| metadata.len() == 0) {
+            let _ = std::fs::remove_file(&partial);
+            let _ = std::fs::remove_file(&validator);
+        }
+        return Err(e);
+    }
+
+    let _ = std::fs::remove_file(&validator);
+    std::fs::rename(&partial, path).map_err(|e| Error::io(path, e))
+}
+
+/// Where `path` is kept while it is being downloaded
+fn partial(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".partial");
+    path.with_file_name(name)
+}
+
+/// Where the validator of a partial download is kept
+fn validator(partial: &Path) -> PathBuf {
+    let mut name = partial.file_name().unwrap_or_default().to_os_string();
+    name.push(".validator");
+    partial.with_file_name(name)
+}
+
+/// The first byte and the length of the whole file from a `Content-Range`
+/// header such as `bytes 200-999/1000`, the length may be unknown (`*`)
+fn content_range(range: &str) -> Option<(u64, Option<u64>)> {
+    let (range, total) = range.trim().strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()))
+}
+
+/// Try `attempt` again while it fails in a way which may not happen again
+fn retry<T>(mut attempt: impl FnMut() -> Result<T, Error>) -> Result<T, Error> {
+    let mut wait = BACKOFF;
+    let mut retries = 0;
+    loop {
+        match attempt() {
+            Err(e) if e.is_network() && retries < RETRIES => {
+                retries += 1;
+                log::warn!("{}, trying again in {}ms", e, wait.as_millis());
+                std::thread::sleep(wait);
+                wait *= 2;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Make a GET request, following redirects to the response which has a body
+fn follow(
+    client: &dyn HttpClient,
+    url: &str,
+    headers: &[Header],
+    body: &mut dyn Body,
+) -> Result<Response, Error> {
+    let mut url = url.to_string();
+    for _ in 0..=REDIRECTS {
+        let response = client.stream(&url, headers, &mut Redirected(body))?;
+        if !redirect(response.status) {
+            return Ok(response);
+        }
+        let location = response.header("Location").ok_or_else(|| {
+            Error::Template(format!(
+                "unable to download {}, redirected without a location",
+                url
+            ))
+        })?;
+        // Locations may be relative to the URL which redirected
+        let next = surf::Url::parse(&url)
+            .and_then(|base| base.join(location))
+            .map_err(|e| {
+                Error::Template(format!(
+                    "unable to download {}, redirected to {} which is not a URL: {}",
+                    url, location, e
+                ))
+            })?;
+        log::info!("redirected from {} to {}", url, next);
+        url = next.to_string();
+    }
+    Err(Error::Template(format!(
+        "unable to download {}, redirected more than {} times",
+        url, REDIRECTS
+    )))
+}
+
+fn redirect(status: u16) -> bool {
+    matches!(status, 301 | 302 | 303 | 307 | 308)
+}
+
+/// Skips the bodies of redirects
+struct Redirected<'a>(&'a mut dyn Body);
+
+impl Body for Redirected<'_> {
+    fn start(&mut self, response: &Response) -> Result<bool, Error> {
+        if redirect(response.status) {
+            return Ok(false);
+        }
+        self.0.start(response)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.0.write(chunk)
+    }
+}
+
+/// Turn an unsuccessful response into an error, those which may be gone by
+/// the next attempt are network errors so they are tried again
+fn check(url: &str, response: &Response) -> Result<(), Error> {
+    match response.status {
+        status if (200..300).contains(&status) => Ok(()),
+        status @ (408 | 429 | 500 | 502 | 503 | 504) => Err(Error::Network {
+            url: url.to_string(),
+            message: format!("the server answered {}", status),
+        }),
+        // The server is up, there is just nothing to download
+        status => Err(Error::Template(format!(
+            "unable to download {}, the server answered {}",
+            url, status
+        ))),
+    }
+}
+
+/// A file being downloaded
+struct Partial {
+    path: PathBuf,
+    /// Where the `ETag` or `Last-Modified` of the download is kept
+    validator: PathBuf,
+    file: File,
+    written: u64,
+    /// The size of the whole file, when the server says
+    total: Option<u64>,
+    progress: Option<Progress>,
+    /// Whether the server answered with a part of the file which does not
+    /// carry on from what was downloaded
+    mismatched: bool,
+}
 
-                return Ok(res.body_bytes().await?);
+impl Partial {
+    fn open(path: &Path) -> Result<Partial, Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| Error::io(path, e))?;
+        let written = file.metadata().map_err(|e| Error::io(path, e))?.len();
+        Ok(Partial {
+            path: path.to_path_buf(),
+            validator: validator(path),
+            file,
+            written,
+            total: None,
+            progress: None,
+            mismatched: false,
+        })
+    }
+
+    /// Throw away what has been downloaded
+    fn restart(&mut self) -> Result<(), Error> {
+        self.file.set_len(0).map_err(|e| Error::io(&self.path, e))?;
+        self.written = 0;
+        let _ = std::fs::remove_file(&self.validator);
+        Ok(())
+    }
+
+    /// Remember what identifies the version of the file `response` is for,
+    /// a strong `ETag` or else `Last-Modified`
+    fn remember(&self, response: &Response) -> Result<(), Error> {
+        let validator = response
+            .header("ETag")
+            .filter(|etag| !etag.starts_with("W/"))
+            .or_else(|| response.header("Last-Modified"));
+        match validator {
+            Some(validator) => std::fs::write(&self.validator, validator)
+                .map_err(|e| Error::io(&self.validator, e)),
+            None => {
+                let _ = std::fs::remove_file(&self.validator);
+                Ok(())
+            }
+        }
+    }
+
+    /// Make sure all of the file arrived
+    fn finish(mut self, url: &str) -> Result<(), Error> {
+        if let Some(progress) = self.progress.take() {
+            progress.clear();
+        }
+        self.file.sync_all().map_err(|e| Error::io(&self.path, e))?;
+        match self.total {
+            Some(total) if total != self.written => Err(Error::Network {
+                url: url.to_string(),
+                message: format!(
+                    "the download ended after {} of {} bytes",
+                    self.written, total
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Body for Partial {
+    fn start(&mut self, response: &Response) -> Result<bool, Error> {
+        let length = response
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok());
+        match response.status {
+            // The rest of the file, if it starts where the partial download ends
+            206 => match response.header("Content-Range").and_then(content_range) {
+                Some((start, total)) if start == self.written => {
+                    self.total = total.or_else(|| length.map(|length| length + self.written));
+                }
+                _ => {
+                    self.mismatched = true;
+                    return Ok(false);
+                }
             },
-            Err(e) => return Err(e),
+            // The server does not do ranges, or the file has changed, so
+            // start from the beginning
+            200 => {
+                self.restart()?;
+                self.remember(response)?;
+                self.total = length;
+            }
+            _ => return Ok(false),
         }
-    })
+        self.progress = Progress::new(&self.path);
+        Ok(true)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.file
+            .write_all(chunk)
+            .map_err(|e| Error::io(&self.path, e))?;
+        self.written += chunk.len() as u64;
+        if let Some(progress) = &mut self.progress {
+            progress.draw(self.written, self.total);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Partial {
+    fn drop(&mut self) {
+        if let Some(progress) = self.progress.take() {
+            progress.clear();
+        }
+    }
+}
+
+/// A progress bar on stderr
+struct Progress {
+    name: String,
+    drawn: Option<Instant>,
+}
+
+impl Progress {
+    /// A progress bar for downloading `path`, unless stderr is not a terminal
+    fn new(path: &Path) -> Option<Progress> {
+        if output::quiet() || !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let name = path.file_name()?.to_string_lossy();
+        let name = name.trim_end_matches(".partial").to_string();
+        Some(Progress { name, drawn: None })
+    }
+
+    fn draw(&mut self, written: u64, total: Option<u64>) {
+        if self.drawn.is_some_and(|drawn| drawn.elapsed() < REDRAW) {
+            return;
+        }
+        self.drawn = Some(Instant::now());
+
+        let line = match total.filter(|total| *total > 0) {
+            Some(total) => {
+                let width = 30;
+                let done = (written.min(total) * width / total) as usize;
+                format!(
+                    "{} [{}{}] {}% {}/{}",
+                    self.name,
+                    "#".repeat(done),
+                    " ".repeat(width as usize - done),
+                    written.min(total) * 100 / total,
+                    cache::human_size(written),
+                    cache::human_size(total)
+                )
+            }
+            None => format!("{} {}", self.name, cache::human_size(written)),
+        };
+        eprint!("\r\x1b[K{}", line);
+    }
+
+    fn clear(self) {
+        if self.drawn.is_some() {
+            eprint!("\r\x1b[K");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+    use crate::cli::http::Mock;
+
+    fn redirect(status: u16, location: &str) -> Response {
+        Response {
+            status,
+            ..Response::default()
+        }
+        .with_header("Location", location)
+    }
+
+    #[test]
+    fn get_works() {
+        let client = Mock::new()
+            .route("http://a.test/latest", redirect(301, "http://b.test/v1"))
+            .route("http://b.test/v1", redirect(307, "files/v1.zip"))
+            .route("http://b.test/files/v1.zip", Response::ok("zip"))
+            .route("http://a.test/loop", redirect(302, "/loop"))
+            .route(
+                "http://a.test/busy",
+                Response {
+                    status: 503,
+                    ..Response::default()
+                },
+            );
+
+        assert_eq!(get(&client, "http://a.test/latest").unwrap(), b"zip");
+        assert_eq!(
+            client.requested(),
+            vec![
+                "http://a.test/latest",
+                "http://b.test/v1",
+                "http://b.test/files/v1.zip"
+            ]
+        );
+
+        let looped = get(&client, "http://a.test/loop").unwrap_err();
+        assert!(looped.to_string().contains("redirected more than 10 times"));
+        let missing = get(&client, "http://a.test/missing").unwrap_err();
+        assert!(matches!(missing, Error::Template(_)));
+
+        let before = client.requested().len();
+        assert!(get(&client, "http://a.test/busy").unwrap_err().is_network());
+        assert_eq!(client.requested().len() - before, RETRIES as usize + 1);
+    }
+
+    #[test]
+    fn to_file_works() {
+        let dir = scratch("download");
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+        let client = Mock::new()
+            .route(
+                "http://a.test/file.zip",
+                Response::ok(data.clone()).with_header("ETag", "\"v1\""),
+            )
+            .interrupt("http://a.test/file.zip", 40_000);
+
+        // The connection drops part of the way through, the rest is asked for
+        let path = dir.join("file.zip");
+        to_file(&client, "http://a.test/file.zip", &path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), data);
+        assert!(!partial(&path).exists());
+        assert!(!validator(&partial(&path)).exists());
+        let headers = client.headers();
+        assert_eq!(headers.len(), 2);
+        assert!(headers[1].contains(&("Range", "bytes=40000-".to_string())));
+        assert!(headers[1].contains(&("If-Range", "\"v1\"".to_string())));
+
+        // Left over from an attempt at another file
+        let other = dir.join("other.zip");
+        std::fs::write(partial(&other), vec![0; 200_000]).unwrap();
+        std::fs::write(validator(&partial(&other)), "\"v1\"").unwrap();
+        to_file(&client, "http://a.test/file.zip", &other).unwrap();
+        assert_eq!(std::fs::read(&other).unwrap(), data);
+
+        // Nothing says what the partial download was of, so it is not resumed
+        let unknown = dir.join("unknown.zip");
+        std::fs::write(partial(&unknown), vec![0; 50_000]).unwrap();
+        let before = client.headers().len();
+        to_file(&client, "http://a.test/file.zip", &unknown).unwrap();
+        assert_eq!(std::fs::read(&unknown).unwrap(), data);
+        let headers = client.headers();
+        assert!(!headers[before].iter().any(|(name, _)| *name == "Range"));
+    }
+
+    #[test]
+    fn to_file_restarts() {
+        let dir = scratch("restart");
+        std::fs::create_dir_all(&dir).unwrap();
+        let old: Vec<u8> = vec![0; 100_000];
+        let new: Vec<u8> = (0..100_000u32).map(|i| (i % 251) as u8).collect();
+
+        // The file changed since, so the server sends all of the new one
+        let client = Mock::new().route(
+            "http://a.test/file.zip",
+            Response::ok(new.clone()).with_header("ETag", "\"v2\""),
+        );
+        let changed = dir.join("changed.zip");
+        std::fs::write(partial(&changed), &old[..40_000]).unwrap();
+        std::fs::write(validator(&partial(&changed)), "\"v1\"").unwrap();
+        to_file(&client, "http://a.test/file.zip", &changed).unwrap();
+        assert_eq!(std::fs::read(&changed).unwrap(), new);
+        assert!(client.headers()[0].contains(&("If-Range", "\"v1\"".to_string())));
+        assert_eq!(client.headers().len(), 1);
+
+        // The server sends a part other than the one asked for
+        let whole = Response {
+            status: 206,
+            body: new.clone(),
+            ..Response::default()
+        }
+        .with_header("Content-Range", "bytes 0-99999/100000");
+        let client = Mock::new().route("http://a.test/file.zip", whole);
+        let elsewhere = dir.join("elsewhere.zip");
+        std::fs::write(partial(&elsewhere), &new[..40_000]).unwrap();
+        std::fs::write(validator(&partial(&elsewhere)), "\"v2\"").unwrap();
+        to_file(&client, "http://a.test/file.zip", &elsewhere).unwrap();
+        assert_eq!(std::fs::read(&elsewhere).unwrap(), new);
+        let headers = client.headers();
+        assert_eq!(headers.len(), 2);
+        assert!(headers[0].contains(&("Range", "bytes=40000-".to_string())));
+        assert!(!headers[1].iter().any(|(name, _)| *name == "Range"));
+    }
 }
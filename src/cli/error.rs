@@ -0,0 +1,155 @@
+//! Errors reported by the command line tool
+//!
+//! Every failure maps to its own exit code so scripts wrapping leap can tell
+//! them apart:
+//!
+//! | code | error                                              |
+//! |------|----------------------------------------------------|
+//! | 2    | the command line could not be understood           |
+//! | 3    | a file could not be read or written                |
+//! | 4    | the config is invalid                              |
+//! | 5    | a server could not be reached                      |
+//! | 6    | the GitHub API answered with an error              |
+//! | 7    | an archive could not be extracted                  |
+//! | 8    | a template is missing, invalid or failed to render |
+//! | 9    | the project could not be found, built or upgraded  |
+//! | 10   | leap could not update itself                       |
+//! | 130  | interrupted with Ctrl-C                            |
+//!
+//! When a served project exits with an error, leap exits with its exit code.
+
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::cli::render::RenderError;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The command line could not be understood
+    Usage(String),
+    /// A file could not be read or written
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The config is invalid
+    Config(String),
+    /// A server could not be reached
+    Network { url: String, message: String },
+    /// The GitHub API answered with an error
+    GitHubApi {
+        url: String,
+        status: u16,
+        message: String,
+    },
+    /// An archive could not be extracted
+    Archive { path: PathBuf, message: String },
+    /// A template is missing, invalid or failed to render
+    Template(String),
+    /// The project could not be found, built or upgraded
+    Project(String),
+    /// leap could not update itself
+    Update(String),
+    /// The user pressed Ctrl-C
+    Interrupted,
+    /// The served project exited with an error
+    Exited(i32),
+}
+
+impl Error {
+    /// A file could not be read or written
+    pub fn io<P: Into<PathBuf>>(path: P, source: std::io::Error) -> Error {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// The exit code leap ends with
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Usage(_) => 2,
+            Error::Io { .. } => 3,
+            Error::Config(_) => 4,
+            Error::Network { .. } => 5,
+            Error::GitHubApi { .. } => 6,
+            Error::Archive { .. } => 7,
+            Error::Template(_) => 8,
+            Error::Project(_) => 9,
+            Error::Update(_) => 10,
+            Error::Interrupted => 130,
+            Error::Exited(code) => *code,
+        }
+    }
+
+    /// The kind of error in `--json` output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::Usage(_) => "usage",
+            Error::Io { .. } => "io",
+            Error::Config(_) => "config",
+            Error::Network { .. } => "network",
+            Error::GitHubApi { .. } => "github_api",
+            Error::Archive { .. } => "archive",
+            Error::Template(_) => "template",
+            Error::Project(_) => "project",
+            Error::Update(_) => "update",
+            Error::Interrupted => "interrupted",
+            Error::Exited(_) => "exited",
+        }
+    }
+
+    /// Did the request fail because the network could not be reached?
+    pub fn is_network(&self) -> bool {
+        matches!(self, Error::Network { .. })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{}", message),
+            Error::Io { path, source } if path.as_os_str().is_empty() => write!(f, "{}", source),
+            Error::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            Error::Config(message) => write!(f, "invalid config, {}", message),
+            Error::Network { url, message } => write!(f, "unable to reach {} ({})", url, message),
+            Error::GitHubApi {
+                url,
+                status,
+                message,
+            } => write!(f, "{} answered {}: {}", url, status, message),
+            Error::Archive { path, message } => {
+                write!(f, "unable to extract {}: {}", path.display(), message)
+            }
+            Error::Template(message) => write!(f, "{}", message),
+            Error::Project(message) => write!(f, "{}", message),
+            Error::Update(message) => write!(f, "unable to update leap, {}", message),
+            Error::Interrupted => write!(f, "interrupted"),
+            Error::Exited(code) => write!(f, "the project exited with code {}", code),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Error::Io {
+            path: PathBuf::new(),
+            source,
+        }
+    }
+}
+
+impl From<RenderError> for Error {
+    fn from(e: RenderError) -> Self {
+        Error::Template(e.to_string())
+    }
+}
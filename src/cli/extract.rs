@@ -0,0 +1,503 @@
+//! Extracting archives safely
+//!
+//! Nothing in an archive is trusted. Entries which would land outside the
+//! directory they are extracted to, symlinks and links are refused rather
+//! than skipped, permissions lose their setuid, setgid, sticky and group or
+//! world write bits, and archives with more entries or bigger contents than
+//! the `extract` limits in the config are refused.
+//!
+//! Archives are extracted next to where they are going and renamed into place
+//! once complete, so a failed or interrupted extraction never leaves half a
+//! directory behind. What an interruption does leave is a hidden
+//! `.<name>.partial-<pid>` directory, which `leap cache clean` removes.
+
+use std::fmt;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+use crate::cli::error::Error;
+use crate::cli::{cache, config};
+
+/// How much of an entry is copied at a time
+const CHUNK: usize = 64 * 1024;
+
+/// Why an archive could not be extracted
+#[derive(Debug)]
+pub enum ExtractError {
+    /// The archive is corrupt, or not the kind it claims to be
+    Unreadable(String),
+    /// An entry would be extracted outside of the destination
+    UnsafePath(String),
+    /// An entry is a symlink or a hard link
+    Link(String),
+    /// An entry is a device, a pipe or something else which is not a file
+    Special(String),
+    /// The archive has more entries than allowed
+    TooManyEntries { limit: usize },
+    /// The archive holds more than allowed once extracted
+    TooLarge { limit: u64 },
+    /// A file could not be written
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl ExtractError {
+    /// A file could not be written
+    pub fn io(path: &Path) -> impl FnOnce(std::io::Error) -> ExtractError {
+        let path = path.to_path_buf();
+        move |source| ExtractError::Io { path, source }
+    }
+
+    /// The error leap reports for extracting `archive`
+    fn at(self, archive: &Path) -> Error {
+        match self {
+            ExtractError::Io { path, source } => Error::io(path, source),
+            e => Error::Archive {
+                path: archive.to_path_buf(),
+                message: e.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExtractError::Unreadable(message) => write!(f, "{}", message),
+            ExtractError::UnsafePath(name) => {
+                write!(f, "{} would be extracted outside of the destination", name)
+            }
+            ExtractError::Link(name) => write!(f, "{} is a link", name),
+            ExtractError::Special(name) => write!(f, "{} is not a file or directory", name),
+            ExtractError::TooManyEntries { limit } => write!(
+                f,
+                "it has more than the {} entries allowed by extract.max_entries",
+                limit
+            ),
+            ExtractError::TooLarge { limit } => write!(
+                f,
+                "it holds more than the {} allowed by extract.max_size",
+                cache::human_size(*limit)
+            ),
+            ExtractError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ExtractError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// How much an archive may hold
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// The most entries, files and directories
+    pub entries: usize,
+    /// The most bytes once extracted
+    pub size: u64,
+}
+
+impl Limits {
+    /// The limits set in the global config
+    pub fn configured() -> Result<Limits, Error> {
+        let extract = config::config()?.extract;
+        Ok(Limits {
+            entries: extract.max_entries,
+            size: extract.max_size,
+        })
+    }
+}
+
+/// Extract the archive at `path` to `extract_to`, which must not exist yet,
+/// with `unpack` writing its entries to a staging directory
+pub fn staged<F>(path: &Path, extract_to: &Path, unpack: F) -> Result<(), Error>
+where
+    F: FnOnce(std::fs::File, &Path) -> Result<(), ExtractError>,
+{
+    let name = extract_to
+        .file_name()
+        .ok_or_else(|| ExtractError::UnsafePath(extract_to.display().to_string()).at(path))?;
+    let staging = extract_to.with_file_name(format!(
+        ".{}.partial-{}",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    let file = std::fs::File::open(path).map_err(|e| Error::io(path, e))?;
+    let extracted = unpack(file, &staging)
+        .and_then(|()| std::fs::rename(&staging, extract_to).map_err(ExtractError::io(extract_to)));
+    if let Err(e) = extracted {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e.at(path));
+    }
+    Ok(())
+}
+
+/// Writes the entries of an archive below a directory, within limits
+pub struct Unpacker {
+    to: PathBuf,
+    limits: Limits,
+    entries: usize,
+    extracted: u64,
+    buffer: Vec<u8>,
+}
+
+impl Unpacker {
+    /// Start unpacking into `to`
+    pub fn new(to: &Path, limits: Limits) -> Result<Unpacker, ExtractError> {
+        std::fs::create_dir_all(to).map_err(ExtractError::io(to))?;
+        Ok(Unpacker {
+            to: to.to_path_buf(),
+            limits,
+            entries: 0,
+            extracted: 0,
+            buffer: vec![0; CHUNK],
+        })
+    }
+
+    /// Count an entry of the archive, and refuse one too many
+    pub fn count(&mut self) -> Result<(), ExtractError> {
+        self.entries += 1;
+        if self.entries > self.limits.entries {
+            return Err(ExtractError::TooManyEntries {
+                limit: self.limits.entries,
+            });
+        }
+        Ok(())
+    }
+
+    /// Where the entry `name` goes, or nothing for the destination itself
+    pub fn path(&self, name: &str) -> Result<Option<PathBuf>, ExtractError> {
+        Ok(entry_path(name)?.map(|relative| self.to.join(relative)))
+    }
+
+    /// Create the directory `path` with the permissions of `mode`
+    pub fn dir(&mut self, path: &Path, mode: Option<u32>) -> Result<(), ExtractError> {
+        log::info!("extracting \"{}\"", path.display());
+        std::fs::create_dir_all(path).map_err(ExtractError::io(path))?;
+        set_permissions(path, mode, true)
+    }
+
+    /// Write the file `path` from `contents`, which claims to be `size` bytes
+    pub fn file(
+        &mut self,
+        path: &Path,
+        size: u64,
+        contents: &mut dyn Read,
+        mode: Option<u32>,
+    ) -> Result<(), ExtractError> {
+        let too_large = ExtractError::TooLarge {
+            limit: self.limits.size,
+        };
+        // The declared size is not trusted, but can refuse an archive early
+        if self.extracted.saturating_add(size) > self.limits.size {
+            return Err(too_large);
+        }
+        log::info!("extracting \"{}\" ({} bytes)", path.display(), size);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(ExtractError::io(parent))?;
+        }
+
+        let mut file = std::fs::File::create(path).map_err(ExtractError::io(path))?;
+        loop {
+            let read = contents
+                .read(&mut self.buffer)
+                .map_err(|e| ExtractError::Unreadable(format!("{}: {}", path.display(), e)))?;
+            if read == 0 {
+                break;
+            }
+            self.extracted += read as u64;
+            if self.extracted > self.limits.size {
+                return Err(too_large);
+            }
+            file.write_all(&self.buffer[..read])
+                .map_err(ExtractError::io(path))?;
+        }
+        set_permissions(path, mode, false)
+    }
+}
+
+/// Give an extracted entry the permissions of `mode`, masked
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: Option<u32>, dir: bool) -> Result<(), ExtractError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    match mode {
+        Some(mode) => std::fs::set_permissions(
+            path,
+            std::fs::Permissions::from_mode(permissions(mode, dir)),
+        )
+        .map_err(ExtractError::io(path)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_: &Path, _: Option<u32>, _: bool) -> Result<(), ExtractError> {
+    Ok(())
+}
+
+/// Where an entry goes below the destination, or nothing for the root itself.
+/// Names which are absolute or climb out of the destination are refused.
+pub fn entry_path(name: &str) -> Result<Option<PathBuf>, ExtractError> {
+    let unsafe_path = || ExtractError::UnsafePath(name.to_string());
+    // Archives made on Windows may separate with backslashes
+    let normalized = name.replace('\\', "/");
+    if normalized.contains('\0') || normalized.contains(':') {
+        return Err(unsafe_path());
+    }
+
+    let mut path = PathBuf::new();
+    for component in Path::new(&normalized).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(unsafe_path())
+            }
+        }
+    }
+    Ok(Some(path).filter(|path| !path.as_os_str().is_empty()))
+}
+
+/// The permissions to give an entry with `mode`, which the owner can always
+/// read and write, and nobody else can write
+pub fn permissions(mode: u32, dir: bool) -> u32 {
+    let owner = if dir { 0o700 } else { 0o600 };
+    (mode & 0o755) | owner
+}
+
+/// An empty directory for a test, removed when dropped so it is not left
+/// behind when the test fails
+#[cfg(test)]
+pub fn scratch(name: &str) -> Scratch {
+    let dir = std::env::temp_dir().join(format!("leap-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    Scratch(dir)
+}
+
+/// The directory made by [`scratch`]
+#[cfg(test)]
+pub struct Scratch(PathBuf);
+
+#[cfg(test)]
+impl std::ops::Deref for Scratch {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for Scratch {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for Scratch {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// An entry of an archive for a test, which each format can make with its
+/// `archive`
+#[cfg(test)]
+pub enum Entry<'a> {
+    Dir(&'a str),
+    /// A file, its contents and its unix mode
+    File(&'a str, &'a [u8], u32),
+    /// A symlink and its target
+    Symlink(&'a str, &'a str),
+}
+
+/// What is left in `dir` besides the archive `archive`, for tests
+#[cfg(test)]
+pub fn left(dir: &Path, archive: &str) -> Vec<String> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|name| name != archive)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: Limits = Limits {
+        entries: 100,
+        size: 1024,
+    };
+
+    /// A format leap extracts, the name of an archive in it and how to make
+    /// and extract one
+    struct Format {
+        archive: &'static str,
+        make: fn(&[Entry<'_>]) -> Vec<u8>,
+        extract: fn(&Path, &Path, Limits) -> Result<(), Error>,
+    }
+
+    const FORMATS: [Format; 2] = [
+        Format {
+            archive: "template.zip",
+            make: crate::cli::zip::archive,
+            extract: crate::cli::zip::extract_with,
+        },
+        Format {
+            archive: "template.tar.gz",
+            make: crate::cli::tar::archive,
+            extract: crate::cli::tar::extract_with,
+        },
+    ];
+
+    impl Format {
+        /// Extract an archive of `entries` to `template` in `dir`
+        fn extract(&self, dir: &Path, entries: &[Entry<'_>], limits: Limits) -> Result<(), Error> {
+            std::fs::create_dir_all(dir).unwrap();
+            let path = dir.join(self.archive);
+            std::fs::write(&path, (self.make)(entries)).unwrap();
+            (self.extract)(&path, &dir.join("template"), limits)
+        }
+    }
+
+    fn file<'a>(name: &'a str, contents: &'a [u8]) -> Entry<'a> {
+        Entry::File(name, contents, 0o644)
+    }
+
+    #[test]
+    fn extract_works() {
+        for format in &FORMATS {
+            let dir = scratch(&format!("extract-works-{}", format.archive));
+            let entries = [
+                Entry::Dir("project/"),
+                file("project/src\\main.rs", b"fn main() {}"),
+                file("./project/README.md", b"# project"),
+            ];
+            assert!(format.extract(&dir, &entries, LIMITS).is_ok());
+            assert_eq!(left(&dir, format.archive), ["template"]);
+            let project = dir.join("template/project");
+            assert_eq!(
+                std::fs::read(project.join("src/main.rs")).unwrap(),
+                b"fn main() {}"
+            );
+            assert!(project.join("README.md").is_file());
+        }
+    }
+
+    #[test]
+    fn extract_refuses() {
+        for format in &FORMATS {
+            let dir = scratch(&format!("extract-refuses-{}", format.archive));
+            let refused = [
+                (
+                    "parent",
+                    file("../escaped", b"!"),
+                    "outside of the destination",
+                ),
+                (
+                    "nested",
+                    file("project/../../escaped", b"!"),
+                    "outside of the destination",
+                ),
+                (
+                    "absolute",
+                    file("/etc/escaped", b"!"),
+                    "outside of the destination",
+                ),
+                (
+                    "drive",
+                    file("C:\\escaped", b"!"),
+                    "outside of the destination",
+                ),
+                (
+                    "symlink",
+                    Entry::Symlink("link", "/etc/passwd"),
+                    "is a link",
+                ),
+            ];
+            for (case, entry, message) in &refused {
+                let case = dir.join(case);
+                let extracted = format.extract(&case, std::slice::from_ref(entry), LIMITS);
+                let error = extracted.unwrap_err();
+                assert!(matches!(error, Error::Archive { .. }), "{:?}", case);
+                assert!(error.to_string().contains(message), "{}", error);
+                assert!(left(&case, format.archive).is_empty(), "{:?}", case);
+            }
+            assert!(!dir.join("escaped").exists());
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn extract_masks_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        for format in &FORMATS {
+            let dir = scratch(&format!("extract-permissions-{}", format.archive));
+            let setuid = [Entry::File("run", b"#!/bin/sh", 0o4777)];
+            assert!(format.extract(&dir, &setuid, LIMITS).is_ok());
+            let metadata = std::fs::metadata(dir.join("template/run")).unwrap();
+            assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+        }
+    }
+
+    #[test]
+    fn extract_limits() {
+        for format in &FORMATS {
+            let dir = scratch(&format!("extract-limits-{}", format.archive));
+            let many = [file("a", b"a"), file("b", b"b"), file("c", b"c")];
+            let few = Limits {
+                entries: 2,
+                ..LIMITS
+            };
+            let extracted = format.extract(&dir.join("many"), &many, few);
+            assert!(extracted
+                .unwrap_err()
+                .to_string()
+                .contains("extract.max_entries"));
+            assert!(left(&dir.join("many"), format.archive).is_empty());
+
+            let bomb = [file("a", &[0; 600]), file("b", &[0; 600])];
+            let extracted = format.extract(&dir.join("bomb"), &bomb, LIMITS);
+            assert!(extracted
+                .unwrap_err()
+                .to_string()
+                .contains("extract.max_size"));
+            assert!(left(&dir.join("bomb"), format.archive).is_empty());
+            let bigger = Limits {
+                size: 1200,
+                ..LIMITS
+            };
+            assert!(format.extract(&dir.join("fits"), &bomb, bigger).is_ok());
+        }
+    }
+
+    #[test]
+    fn entry_path_works() {
+        let path = |name: &str| entry_path(name).map(|path| path.map(PathBuf::into_os_string));
+        assert_eq!(path("a/b.rs").unwrap(), Some("a/b.rs".into()));
+        assert_eq!(path("./a\\b.rs").unwrap(), Some("a/b.rs".into()));
+        assert_eq!(path("./").unwrap(), None);
+        for name in &["../a", "a/../../b", "/etc/passwd", "C:\\a", "a\0b"] {
+            assert!(
+                matches!(path(name), Err(ExtractError::UnsafePath(_))),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(permissions(0o104_777, false), 0o755);
+        assert_eq!(permissions(0o040_555, true), 0o755);
+        assert_eq!(permissions(0o100_444, false), 0o644);
+    }
+}
@@ -1,49 +1,38 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-pub fn copy<U: AsRef<Path>, V: AsRef<Path>>(from: U, to: V) -> Result<(), std::io::Error> {
-    let mut stack = Vec::new();
-    stack.push(PathBuf::from(from.as_ref()));
-
-    let output_root = PathBuf::from(to.as_ref());
-    let input_root = PathBuf::from(from.as_ref()).components().count();
+/// List the files below `root`, relative to `root`
+pub fn files<P: AsRef<Path>>(root: P) -> Result<Vec<PathBuf>, std::io::Error> {
+    let mut stack = vec![PathBuf::from(root.as_ref())];
+    let mut files = Vec::new();
 
     while let Some(working_path) = stack.pop() {
-        log::info!("process: {:?}", &working_path);
-
-        // Generate a relative path
-        let src: PathBuf = working_path.components().skip(input_root).collect();
-
-        // Create a destination if missing
-        let dest = if src.components().count() == 0 {
-            output_root.clone()
-        } else {
-            output_root.join(&src)
-        };
-        if fs::metadata(&dest).is_err() {
-            log::info!(" mkdir: {:?}", dest);
-            fs::create_dir_all(&dest)?;
-        }
-
         for entry in fs::read_dir(working_path)? {
             let entry = entry?;
             let path = entry.path();
-            if path.is_dir() {
+            if path.file_name() == Some(".git".as_ref()) {
+                // A template checked out on disk, its history is not part of it
+                continue;
+            }
+            // Symlinks are listed rather than followed, wherever they point
+            if entry.file_type()?.is_dir() {
                 stack.push(path);
-            } else {
-                match path.file_name() {
-                    Some(filename) => {
-                        let dest_path = dest.join(filename);
-                        log::info!("  copy: {:?} -> {:?}", &path, &dest_path);
-                        fs::copy(&path, &dest_path)?;
-                    }
-                    None => {
-                        log::info!("failed: {:?}", path);
-                    }
-                }
+            } else if let Ok(relative) = path.strip_prefix(root.as_ref()) {
+                files.push(relative.to_path_buf());
             }
         }
     }
 
-    Ok(())
-}
\ No newline at end of file
+    files.sort();
+    Ok(files)
+}
+
+/// The directory holding the files of an unpacked archive. Archives from
+/// GitHub and the like wrap everything in a single directory.
+pub fn root<P: AsRef<Path>>(extracted: P) -> Result<PathBuf, std::io::Error> {
+    let entries = fs::read_dir(extracted.as_ref())?.collect::<Result<Vec<_>, _>>()?;
+    match entries.as_slice() {
+        [entry] if entry.path().is_dir() => Ok(entry.path()),
+        _ => Ok(PathBuf::from(extracted.as_ref())),
+    }
+}
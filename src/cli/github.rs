@@ -1,76 +1,658 @@
-use async_std::task;
+//! Releases and tags from the GitHub API
+//!
+//! Requests are authenticated with `github.token` from the global config, or
+//! `GITHUB_TOKEN` when talking to api.github.com, and the token is only ever
+//! sent to the API it was set for. Requests follow `Link` headers to read
+//! every page. Responses with an `ETag` are kept in the data directory, so
+//! asking again only costs a conditional request, which does not count
+//! against the rate limit when nothing has changed.
+
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::cli::error::Error;
+use crate::cli::http::{Header, HttpClient};
+use crate::cli::{cache, config, lock, template};
+
+/// The API used unless the config says otherwise
+pub const API_URL: &str = "https://api.github.com";
+
+/// The repository the project template is released from
+pub const PROJECT_TEMPLATE_REPOSITORY: &str = "daniel-samson/leap-project-template";
+
+/// The repository leap itself is released from
+pub const LEAP_REPOSITORY: &str = "daniel-samson/leap";
+
+/// Where responses are kept for conditional requests, in the data directory
+pub const ETAG_DIR: &str = "github";
+
+/// The token used for api.github.com when `github.token` is not set
+const TOKEN_VARIABLE: &str = "GITHUB_TOKEN";
+
+/// How many items to ask for in each page, the most GitHub allows
+const PER_PAGE: &str = "per_page=100";
+
+/// A tag of a template repository which names a version, with or without a
+/// leading `v`
 pub struct Tag {
+    /// The version, without any `v`
     pub name: String,
+    /// The tag as it is in the repository
+    pub tag: String,
     pub zipball: String,
+    #[allow(dead_code)]
     pub tarball: String,
     pub sha: String,
 }
 
-/// get the version for the leap repository
-#[allow(dead_code)]
-#[allow(unused)]
-pub fn get_leap_versions() -> Result<Vec<Tag>, Box<dyn std::error::Error + Send + Sync>> {
-    let tags_url = get_tags_url("https://api.github.com/repos/daniel-samson/leap")?;
-    let tags = get_tags(tags_url)?;
-    Ok(get_versioned_tags(tags))
+/// A published release whose tag is a version
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub version: Version,
+    pub tag: String,
+    /// The release notes, in markdown
+    pub notes: String,
+    pub prerelease: bool,
+    pub assets: Vec<Asset>,
+}
+
+/// A file attached to a release
+#[derive(Debug, Clone)]
+pub struct Asset {
+    pub name: String,
+    pub url: String,
+}
+
+/// get the published releases of a repository, e.g. `daniel-samson/leap`
+pub fn get_releases(
+    client: &dyn HttpClient,
+    api_url: &str,
+    repository: &str,
+) -> Result<Vec<Release>, Error> {
+    let releases_url = format!(
+        "{}/repos/{}/releases",
+        api_url.trim_end_matches('/'),
+        repository
+    );
+    let releases = get_all(client, &releases_url, "releases")?;
+    Ok(releases
+        .iter()
+        .filter(|release| !release["draft"].as_bool().unwrap_or(false))
+        .filter_map(release)
+        .collect())
+}
+
+/// get the release of a tag, if it has one
+pub fn get_release(
+    client: &dyn HttpClient,
+    api_url: &str,
+    repository: &str,
+    tag: &str,
+) -> Result<Option<Release>, Error> {
+    let release_url = format!(
+        "{}/repos/{}/releases/tags/{}",
+        api_url.trim_end_matches('/'),
+        repository,
+        tag
+    );
+    match get(client, &release_url) {
+        Ok(page) => Ok(release(&page.body)),
+        Err(Error::GitHubApi { status: 404, .. }) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// A release from the API, unless its tag is not a version
+fn release(release: &Value) -> Option<Release> {
+    let text = |value: &Value, key: &str| value[key].as_str().unwrap_or_default().to_string();
+    let tag = text(release, "tag_name");
+    let version = Version::parse(tag.trim_start_matches('v')).ok()?;
+    let assets = release["assets"].as_array().cloned().unwrap_or_default();
+    Some(Release {
+        version,
+        notes: text(release, "body"),
+        prerelease: release["prerelease"].as_bool().unwrap_or(false),
+        assets: assets
+            .iter()
+            .map(|asset| Asset {
+                name: text(asset, "name"),
+                url: text(asset, "browser_download_url"),
+            })
+            .collect(),
+        tag,
+    })
+}
+
+/// get the versions of a template repository, e.g. `daniel-samson/leap-project-template`
+pub fn get_template_versions(
+    client: &dyn HttpClient,
+    api_url: &str,
+    repository: &str,
+) -> Result<Vec<Tag>, Error> {
+    let tags_url = get_tags_url(
+        client,
+        &format!("{}/repos/{}", api_url.trim_end_matches('/'), repository),
+    )?;
+    let tags = get_tags(client, &tags_url)?;
+    get_versioned_tags(&tags_url, tags)
 }
 
-/// get the versions fro the leap project repository
-pub fn get_leap_project_template_versions(
-) -> Result<Vec<Tag>, Box<dyn std::error::Error + Send + Sync>> {
-    let tags_url =
-        get_tags_url("https://api.github.com/repos/daniel-samson/leap-project-template")?;
-    let tags = get_tags(tags_url)?;
-    Ok(get_versioned_tags(tags))
+/// Parse a version requirement such as `^0.3`. A plain version like `0.3.1`
+/// only matches itself.
+pub fn version_requirement(version: &str) -> Result<VersionReq, Error> {
+    let version = version.trim().trim_start_matches('v');
+    if let Ok(exact) = Version::parse(version) {
+        return Ok(VersionReq::exact(&exact));
+    }
+    VersionReq::parse(version)
+        .map_err(|e| Error::Usage(format!("invalid version '{}': {}", version, e)))
+}
+
+/// The newest release matching `requirement`, prereleases are only considered
+/// when `pre` is set
+pub fn latest<'a>(tags: &'a [Tag], requirement: Option<&VersionReq>, pre: bool) -> Option<&'a Tag> {
+    tags.iter()
+        .filter_map(|tag| Version::parse(&tag.name).ok().map(|version| (version, tag)))
+        .filter(|(version, _)| pre || !version.is_prerelease())
+        .filter(|(version, _)| {
+            requirement.is_none_or(|requirement| {
+                let mut release = version.clone();
+                release.pre.clear();
+                // Let ^0.3 take 0.3.2-beta.1 too when asked for prereleases
+                requirement.matches(version) || (pre && requirement.matches(&release))
+            })
+        })
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, tag)| tag)
 }
 
 /// Get the tags url for a given repository
-fn get_tags_url(api_url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
-    let res = get(api_url)?;
-    let tags_url = res["tags_url"].as_str().expect("failed to get tags_url");
-    Ok(String::from(tags_url))
+fn get_tags_url(client: &dyn HttpClient, api_url: &str) -> Result<String, Error> {
+    let res = get(client, api_url)?.body;
+    match res["tags_url"].as_str() {
+        Some(tags_url) => Ok(String::from(tags_url)),
+        None => Err(unexpected(api_url, "no tags_url")),
+    }
 }
 
 /// Get the tags available
-fn get_tags(tags_url: String) -> Result<Vec<Value>, Box<dyn std::error::Error + Send + Sync>> {
-    let res = get(tags_url.as_str())?;
-    let tags = res.as_array().expect("failed to parse tags");
-    Ok(tags.clone())
+fn get_tags(client: &dyn HttpClient, tags_url: &str) -> Result<Vec<Value>, Error> {
+    get_all(client, tags_url, "tags")
 }
 
 /// Get a list of tags which look like versions
-fn get_versioned_tags(tags: Vec<Value>) -> Vec<Tag> {
-    tags.iter()
-        .map(|tag| {
-            (
-                tag["name"].as_str().unwrap(),
-                tag["zipball_url"].as_str().unwrap(),
-                tag["tarball_url"].as_str().unwrap(),
-                tag["commit"]["sha"].as_str().unwrap(),
-            )
-        })
-        .filter(|(name, _, _, _)| name.starts_with('v'))
-        .map(|(name, zipball, tarball, sha)| Tag {
-            name: String::from(name.trim_start_matches('v')),
-            zipball: String::from(zipball),
-            tarball: String::from(tarball),
-            sha: String::from(sha),
-        })
-        .collect()
+fn get_versioned_tags(tags_url: &str, tags: Vec<Value>) -> Result<Vec<Tag>, Error> {
+    let field = |tag: &Value, pointer: &str| match tag.pointer(pointer).and_then(Value::as_str) {
+        Some(value) => Ok(String::from(value)),
+        None => Err(unexpected(tags_url, &format!("a tag has no {}", pointer))),
+    };
+
+    let mut versioned = Vec::new();
+    for tag in &tags {
+        let name = field(tag, "/name")?;
+        let version = name.strip_prefix('v').unwrap_or(&name);
+        if Version::parse(version).is_err() {
+            continue;
+        }
+        versioned.push(Tag {
+            name: String::from(version),
+            tag: name.clone(),
+            zipball: field(tag, "/zipball_url")?,
+            tarball: field(tag, "/tarball_url")?,
+            sha: field(tag, "/commit/sha")?,
+        });
+    }
+    Ok(versioned)
+}
+
+/// The API answered successfully, but not with what was expected
+fn unexpected(url: &str, message: &str) -> Error {
+    Error::GitHubApi {
+        url: url.to_string(),
+        status: 200,
+        message: format!("unexpected response, {}", message),
+    }
+}
+
+/// A page of a response
+struct Page {
+    body: Value,
+    /// The URL of the next page
+    next: Option<String>,
+}
+
+/// A response kept for conditional requests
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    etag: String,
+    next: Option<String>,
+    body: Value,
+}
+
+/// Every item of a list, following the pages of the response
+fn get_all(client: &dyn HttpClient, url: &str, items: &str) -> Result<Vec<Value>, Error> {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let mut next = Some(format!("{}{}{}", url, separator, PER_PAGE));
+    let mut all = Vec::new();
+    while let Some(url) = next {
+        let page = get(client, &url)?;
+        match page.body {
+            Value::Array(list) => all.extend(list),
+            _ => return Err(unexpected(&url, &format!("{} are not a list", items))),
+        }
+        next = page.next;
+    }
+    Ok(all)
 }
 
 /// Make a GET request
-fn get(url: &str) -> Result<Value, Box<dyn std::error::Error + Send + Sync>> {
-    task::block_on(async {
-        let response = surf::get(url)
-            .set_header("Accept", "application/vnd.github.v3+json")
-            .set_header("User-Agent", "https://leap.rs/")
-            .recv_string()
-            .await?;
-        log::info!("body: {}", &response);
-        let v: Value = serde_json::from_str(response.as_str()).unwrap();
-        Ok(v)
+fn get(client: &dyn HttpClient, url: &str) -> Result<Page, Error> {
+    let saved = saved(url);
+    let headers = headers(token(url), saved.as_ref().map(|saved| saved.etag.as_str()));
+    let response = client.get(url, &headers)?;
+
+    let status = response.status;
+    if let (304, Some(saved)) = (status, saved) {
+        log::info!("not modified: {}", url);
+        return Ok(Page {
+            body: saved.body,
+            next: saved.next,
+        });
+    }
+    let etag = response.header("ETag").map(String::from);
+    let next = response.header("Link").and_then(next_link);
+    let limited = rate_limited(
+        status,
+        response.header("X-RateLimit-Remaining"),
+        response.header("X-RateLimit-Reset"),
+        response.header("Retry-After"),
+        template::now(),
+        token(url).is_some(),
+    );
+    let body = String::from_utf8_lossy(&response.body);
+    log::info!("body: {}", &body);
+
+    let v: Value = serde_json::from_str(&body).unwrap_or(Value::Null);
+    if let Some(message) = limited {
+        return Err(Error::GitHubApi {
+            url: url.to_string(),
+            status,
+            message,
+        });
+    }
+    if !response.is_success() {
+        return Err(Error::GitHubApi {
+            url: url.to_string(),
+            status,
+            message: v["message"]
+                .as_str()
+                .unwrap_or("request failed")
+                .to_string(),
+        });
+    }
+    if v.is_null() {
+        return Err(unexpected(url, "the body is not JSON"));
+    }
+
+    if let Some(etag) = etag {
+        save(
+            url,
+            &Saved {
+                etag,
+                next: next.clone(),
+                body: v.clone(),
+            },
+        );
+    }
+    Ok(Page { body: v, next })
+}
+
+/// The headers of a request, authenticated with `token` and conditional on `etag`
+fn headers(token: Option<&str>, etag: Option<&str>) -> Vec<Header> {
+    let mut headers = vec![("Accept", "application/vnd.github.v3+json".to_string())];
+    if let Some(token) = token {
+        headers.push(("Authorization", format!("Bearer {}", token)));
+    }
+    if let Some(etag) = etag {
+        headers.push(("If-None-Match", etag.to_string()));
+    }
+    headers
+}
+
+/// A token and the API it may be sent to
+#[derive(Debug)]
+struct Credentials {
+    api_url: String,
+    token: String,
+}
+
+/// The credentials from the config, or `GITHUB_TOKEN` for api.github.com.
+/// A token is only used with an API set in the same place, so a token set
+/// for one server never goes to another.
+fn credentials(layered: &config::Layered, env_token: Option<String>) -> Option<Credentials> {
+    let config::GithubConfig { api_url, token } = &layered.config.github;
+    let origin = |key: &str| layered.origins.get(key).unwrap_or(&config::Origin::Default);
+    let trusted = match origin("github.api_url") {
+        config::Origin::Default => true,
+        api_origin => origin("github.token") == api_origin,
+    };
+    let token = Some(token.trim())
+        .filter(|token| trusted && !token.is_empty())
+        .map(String::from)
+        // A token for github.com is no business of other servers
+        .or_else(|| env_token.filter(|_| same_api(API_URL, api_url)))?;
+    Some(Credentials {
+        api_url: api_url.clone(),
+        token: token.trim().to_string(),
+    })
+}
+
+/// The token to send with a request to `url`, only ever sent to the configured API
+fn token(url: &str) -> Option<&'static str> {
+    static CREDENTIALS: OnceLock<Option<Credentials>> = OnceLock::new();
+    CREDENTIALS
+        .get_or_init(|| credentials(&config::layered().ok()?, std::env::var(TOKEN_VARIABLE).ok()))
+        .as_ref()
+        .and_then(|credentials| authorizes(credentials, url))
+}
+
+/// The token of `credentials` when `url` belongs to their API
+fn authorizes<'a>(credentials: &'a Credentials, url: &str) -> Option<&'a str> {
+    Some(credentials.token.as_str()).filter(|_| same_api(url, &credentials.api_url))
+}
+
+/// Is `url` on the same scheme, host and port as `api_url`, and below its path?
+fn same_api(url: &str, api_url: &str) -> bool {
+    let (url, api_url) = match (surf::Url::parse(url), surf::Url::parse(api_url)) {
+        (Ok(url), Ok(api_url)) => (url, api_url),
+        _ => return false,
+    };
+    let base = api_url.path().trim_end_matches('/');
+    url.scheme() == api_url.scheme()
+        && url.host_str() == api_url.host_str()
+        && url.port_or_known_default() == api_url.port_or_known_default()
+        && (url.path() == base || url.path().starts_with(&format!("{}/", base)))
+}
+
+/// The URL of the next page from a `Link` header
+fn next_link(link: &str) -> Option<String> {
+    link.split(',').find_map(|link| {
+        let mut parts = link.split(';');
+        let url = parts.next()?.trim();
+        let next = parts.any(|part| {
+            let part = part.trim();
+            part == "rel=\"next\"" || part == "rel=next"
+        });
+        Some(
+            url.trim_start_matches('<')
+                .trim_end_matches('>')
+                .to_string(),
+        )
+        .filter(|_| next)
     })
 }
+
+/// Why GitHub refused a request because of its rate limit, if it did
+fn rate_limited(
+    status: u16,
+    remaining: Option<&str>,
+    reset: Option<&str>,
+    retry_after: Option<&str>,
+    now: u64,
+    authenticated: bool,
+) -> Option<String> {
+    let exhausted = remaining.map(str::trim) == Some("0");
+    if status != 429 && !(status == 403 && (exhausted || retry_after.is_some())) {
+        return None;
+    }
+
+    let wait = retry_after
+        .and_then(|seconds| seconds.trim().parse::<u64>().ok())
+        .or_else(|| {
+            let reset: u64 = reset?.trim().parse().ok()?;
+            Some(reset.saturating_sub(now))
+        });
+    let mut message = "the rate limit is used up".to_string();
+    if let Some(wait) = wait {
+        let at = now + wait;
+        let wait = match wait {
+            0..=90 => format!("{} seconds", wait),
+            91..=5_400 => format!("{} minutes", wait.div_ceil(60)),
+            _ => format!("{} hours", wait.div_ceil(3_600)),
+        };
+        message.push_str(&format!(
+            ", it resets in {} at {} {:02}:{:02} UTC",
+            wait,
+            template::date(at),
+            at % 86_400 / 3_600,
+            at % 3_600 / 60
+        ));
+    }
+    if !authenticated {
+        message.push_str(", set GITHUB_TOKEN or github.token in the config to raise it");
+    }
+    Some(message)
+}
+
+/// Where the response to `url` is kept
+fn saved_path(url: &str) -> Option<PathBuf> {
+    let hash = lock::hash(url.as_bytes());
+    let name = format!("{}.json", hash.trim_start_matches("sha256:"));
+    cache::dir().ok().map(|dir| dir.join(ETAG_DIR).join(name))
+}
+
+/// The response kept for `url`, if there is one
+fn saved(url: &str) -> Option<Saved> {
+    let text = std::fs::read_to_string(saved_path(url)?).ok()?;
+    serde_json::from_str(&text).ok()
+}
+
+/// Keep a response for conditional requests, a response which cannot be kept
+/// is only asked for in full next time
+fn save(url: &str, saved: &Saved) {
+    let path = match saved_path(url) {
+        Some(path) => path,
+        None => return,
+    };
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, serde_json::to_vec(saved)?));
+    if let Err(e) = written {
+        log::warn!("unable to keep {} because {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::http::{Mock, Response};
+
+    fn tag(name: &str) -> Tag {
+        Tag {
+            name: name.to_string(),
+            tag: format!("v{}", name),
+            zipball: String::new(),
+            tarball: String::new(),
+            sha: String::new(),
+        }
+    }
+
+    #[test]
+    fn latest_works() {
+        let tags: Vec<Tag> = [
+            "0.2.0",
+            "0.10.0",
+            "0.3.1",
+            "0.3.2-beta.1",
+            "0.3.0",
+            "1.0.0-rc.1",
+        ]
+        .iter()
+        .map(|name| tag(name))
+        .collect();
+        let name = |requirement: &str, pre: bool| {
+            let requirement = version_requirement(requirement).unwrap();
+            latest(&tags, Some(&requirement), pre).map(|tag| tag.name.as_str())
+        };
+
+        assert_eq!(latest(&tags, None, false).unwrap().name, "0.10.0");
+        assert_eq!(latest(&tags, None, true).unwrap().name, "1.0.0-rc.1");
+        assert_eq!(name("0.3.0", false), Some("0.3.0"));
+        assert_eq!(name("v0.3.1", false), Some("0.3.1"));
+        assert_eq!(name("^0.3", false), Some("0.3.1"));
+        assert_eq!(name("^0.3", true), Some("0.3.2-beta.1"));
+        assert_eq!(name("^2", false), None);
+        assert!(version_requirement("latest").is_err());
+    }
+
+    #[test]
+    fn get_template_versions_works() {
+        let api_url = "http://github.test";
+        let tags_url = "http://github.test/repos/owner/template/tags";
+        let tag = |name: &str| {
+            serde_json::json!({
+                "name": name,
+                "zipball_url": format!("{}/zipball/{}", api_url, name),
+                "tarball_url": format!("{}/tarball/{}", api_url, name),
+                "commit": { "sha": format!("{}-sha", name) },
+            })
+        };
+        let next = format!("<{}?per_page=100&page=2>; rel=\"next\"", tags_url);
+        let client = Mock::new()
+            .json(
+                "http://github.test/repos/owner/template",
+                serde_json::json!({ "tags_url": tags_url }),
+            )
+            .route(
+                &format!("{}?per_page=100", tags_url),
+                Response::ok(
+                    serde_json::json!([tag("v0.2.0"), tag("docs"), tag("v2-beta")]).to_string(),
+                )
+                .with_header("Link", &next),
+            )
+            .json(
+                &format!("{}?per_page=100&page=2", tags_url),
+                serde_json::json!([tag("0.3.0"), tag("v0.4.0-beta.1"), tag("vv0.5.0")]),
+            );
+
+        let versions = get_template_versions(&client, api_url, "owner/template").unwrap();
+        let names: Vec<&str> = versions.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(names, vec!["0.2.0", "0.3.0", "0.4.0-beta.1"]);
+        let newest = latest(&versions, None, false).unwrap();
+        assert_eq!(newest.tag, "0.3.0");
+        assert_eq!(newest.sha, "0.3.0-sha");
+        assert_eq!(newest.zipball, "http://github.test/zipball/0.3.0");
+        assert_eq!(versions[0].tag, "v0.2.0");
+        assert_eq!(client.requested().len(), 3);
+        assert!(client.headers()[0]
+            .iter()
+            .any(|(name, value)| *name == "Accept" && value.contains("github")));
+
+        let missing = get_template_versions(&client, api_url, "owner/missing");
+        assert!(matches!(missing, Err(Error::GitHubApi { status: 404, .. })));
+        let offline = get_template_versions(&Mock::offline(), api_url, "owner/template");
+        assert!(matches!(offline, Err(e) if e.is_network()));
+    }
+
+    #[test]
+    fn credentials_work() {
+        let global = config::Origin::Global(PathBuf::from("/home/cli.toml"));
+        let layered = |api_url: &str, api_origin: config::Origin| {
+            let mut config = config::Config::default();
+            config.github.api_url = api_url.to_string();
+            config.github.token = "secret".to_string();
+            let origins = vec![
+                ("github.api_url".to_string(), api_origin),
+                ("github.token".to_string(), global.clone()),
+            ]
+            .into_iter()
+            .collect();
+            config::Layered { config, origins }
+        };
+        let authorization = |credentials: Option<Credentials>, url: &str| {
+            let token = credentials.as_ref().and_then(|c| authorizes(c, url));
+            headers(token, None)
+                .into_iter()
+                .find(|(name, _)| *name == "Authorization")
+                .map(|(_, value)| value)
+        };
+
+        // An API set by a project never gets the global token
+        let project = config::Origin::Project(PathBuf::from("/project/leap.toml"));
+        let evil = layered("https://evil.test", project);
+        let url = "https://evil.test/repos/owner/template";
+        assert_eq!(authorization(credentials(&evil, None), url), None);
+        let env = Some("from-env".to_string());
+        assert_eq!(authorization(credentials(&evil, env.clone()), url), None);
+
+        let default = layered(API_URL, config::Origin::Default);
+        let url = "https://api.github.com/repos/owner/template";
+        let bearer = Some("Bearer secret".to_string());
+        assert_eq!(authorization(credentials(&default, None), url), bearer);
+        for elsewhere in &[
+            "https://api.github.com.evil.tld/repos/owner/template",
+            "http://api.github.com/repos/owner/template",
+            "https://api.github.com:8443/repos/owner/template",
+        ] {
+            assert_eq!(
+                authorization(credentials(&default, None), elsewhere),
+                None,
+                "{}",
+                elsewhere
+            );
+        }
+
+        // GITHUB_TOKEN is only for api.github.com
+        let mut unset = layered(API_URL, config::Origin::Default);
+        unset.config.github.token = String::new();
+        let bearer = Some("Bearer from-env".to_string());
+        assert_eq!(authorization(credentials(&unset, env.clone()), url), bearer);
+        let mut enterprise = layered("https://git.example.com/api/v3", global.clone());
+        enterprise.config.github.token = String::new();
+        assert!(credentials(&enterprise, env).is_none());
+        let enterprise = layered("https://git.example.com/api/v3", global.clone());
+        let below = "https://git.example.com/api/v3/repos/owner/template";
+        assert!(authorization(credentials(&enterprise, None), below).is_some());
+        let beside = "https://git.example.com/api/v30/repos/owner/template";
+        assert_eq!(authorization(credentials(&enterprise, None), beside), None);
+    }
+
+    #[test]
+    fn next_link_works() {
+        let link = "<https://api.github.com/repositories/1/tags?page=2>; rel=\"next\", \
+                    <https://api.github.com/repositories/1/tags?page=5>; rel=\"last\"";
+        assert_eq!(
+            next_link(link).as_deref(),
+            Some("https://api.github.com/repositories/1/tags?page=2")
+        );
+        let last = "<https://api.github.com/repositories/1/tags?page=1>; rel=\"prev\", \
+                    <https://api.github.com/repositories/1/tags?page=1>; rel=\"first\"";
+        assert_eq!(next_link(last), None);
+    }
+
+    #[test]
+    fn rate_limited_works() {
+        let now = 1_700_000_000;
+        let reset = (now + 600).to_string();
+        let limited = rate_limited(403, Some("0"), Some(&reset), None, now, false).unwrap();
+        assert!(limited.contains("resets in 10 minutes at 2023-11-14 22:23 UTC"));
+        assert!(limited.contains("GITHUB_TOKEN"));
+        let limited = rate_limited(429, None, None, Some("30"), now, true).unwrap();
+        assert!(limited.contains("resets in 30 seconds"));
+        assert!(!limited.contains("GITHUB_TOKEN"));
+        // Forbidden for other reasons
+        assert_eq!(
+            rate_limited(403, Some("42"), Some(&reset), None, now, false),
+            None
+        );
+        assert_eq!(
+            rate_limited(200, Some("0"), Some(&reset), None, now, false),
+            None
+        );
+    }
+}
@@ -0,0 +1,298 @@
+//! How leap talks HTTP
+//!
+//! Downloads and the GitHub API go through an [`HttpClient`], which is
+//! [`Surf`] when leap runs. Tests hand in a [`Mock`] answering from fixtures
+//! instead, so nothing they do reaches the network.
+
+use std::time::Duration;
+
+use async_std::io::ReadExt;
+use async_std::task;
+
+use crate::cli::error::Error;
+
+/// How long to wait for a server to answer, or for more of a body
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How much of a body is read at a time
+const CHUNK: usize = 64 * 1024;
+
+/// A response to a GET request, whatever its status
+#[derive(Debug, Clone, Default)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// The value of a header, whatever its case
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// A request header, its name and value
+pub type Header = (&'static str, String);
+
+/// Takes the body of a response as it arrives
+pub trait Body {
+    /// Whether to read the body of `response`, which has not arrived yet
+    fn start(&mut self, response: &Response) -> Result<bool, Error>;
+
+    /// Take the next part of the body
+    fn write(&mut self, chunk: &[u8]) -> Result<(), Error>;
+}
+
+/// Keeps the whole body
+impl Body for Vec<u8> {
+    fn start(&mut self, _: &Response) -> Result<bool, Error> {
+        Ok(true)
+    }
+
+    fn write(&mut self, chunk: &[u8]) -> Result<(), Error> {
+        self.extend_from_slice(chunk);
+        Ok(())
+    }
+}
+
+/// Makes HTTP requests
+pub trait HttpClient {
+    /// Make a GET request with extra `headers`, reading the whole body into
+    /// the response. Only fails when the server cannot be reached, error
+    /// statuses are left to the caller.
+    fn get(&self, url: &str, headers: &[Header]) -> Result<Response, Error> {
+        let mut body = Vec::new();
+        let mut response = self.stream(url, headers, &mut body)?;
+        response.body = body;
+        Ok(response)
+    }
+
+    /// Make a GET request, handing the body to `body` as it arrives rather
+    /// than keeping it in the response
+    fn stream(&self, url: &str, headers: &[Header], body: &mut dyn Body)
+        -> Result<Response, Error>;
+}
+
+/// Makes requests over the network with surf
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Surf;
+
+impl HttpClient for Surf {
+    fn stream(
+        &self,
+        url: &str,
+        headers: &[Header],
+        body: &mut dyn Body,
+    ) -> Result<Response, Error> {
+        let network = |message: String| Error::Network {
+            url: url.to_string(),
+            message,
+        };
+        let timed_out = |_| network(format!("no answer in {} seconds", TIMEOUT.as_secs()));
+
+        task::block_on(async {
+            let mut request = surf::get(url).header("User-Agent", "https://leap.rs/");
+            for (name, value) in headers {
+                request = request.header(*name, value.as_str());
+            }
+            let mut response = async_std::future::timeout(TIMEOUT, request)
+                .await
+                .map_err(timed_out)?
+                .map_err(|e| network(e.to_string()))?;
+            let answered = Response {
+                status: response.status() as u16,
+                headers: response
+                    .iter()
+                    .map(|(name, values)| (name.to_string(), values.as_str().to_string()))
+                    .collect(),
+                body: Vec::new(),
+            };
+
+            if body.start(&answered)? {
+                let mut buffer = vec![0; CHUNK];
+                loop {
+                    let read = async_std::future::timeout(TIMEOUT, response.read(&mut buffer))
+                        .await
+                        .map_err(timed_out)?
+                        .map_err(|e| network(e.to_string()))?;
+                    if read == 0 {
+                        break;
+                    }
+                    body.write(&buffer[..read])?;
+                }
+            }
+            Ok(answered)
+        })
+    }
+}
+
+#[cfg(test)]
+impl Response {
+    /// A `200 OK` with `body`
+    pub fn ok<B: Into<Vec<u8>>>(body: B) -> Response {
+        Response {
+            status: 200,
+            headers: Vec::new(),
+            body: body.into(),
+        }
+    }
+
+    /// The same response with another header
+    pub fn with_header(mut self, name: &str, value: &str) -> Response {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+/// Answers requests from fixtures, for tests
+#[cfg(test)]
+#[derive(Default)]
+pub struct Mock {
+    routes: Vec<(String, Response)>,
+    requests: std::cell::RefCell<Vec<(String, Vec<Header>)>>,
+    interruptions: std::cell::RefCell<Vec<(String, usize)>>,
+    offline: bool,
+}
+
+#[cfg(test)]
+impl Mock {
+    /// A mock which answers 404 until given routes
+    pub fn new() -> Mock {
+        Mock::default()
+    }
+
+    /// A mock which behaves like a network which cannot be reached
+    pub fn offline() -> Mock {
+        Mock {
+            offline: true,
+            ..Mock::default()
+        }
+    }
+
+    /// Answer `url` with `response`. URLs are matched exactly first, then
+    /// without their query.
+    pub fn route(mut self, url: &str, response: Response) -> Mock {
+        self.routes.push((url.to_string(), response));
+        self
+    }
+
+    /// Answer `url` with JSON
+    pub fn json(self, url: &str, json: serde_json::Value) -> Mock {
+        self.route(url, Response::ok(json.to_string()))
+    }
+
+    /// Drop the connection the next time `url` is requested, after sending
+    /// `after` bytes of its body
+    pub fn interrupt(self, url: &str, after: usize) -> Mock {
+        self.interruptions
+            .borrow_mut()
+            .push((url.to_string(), after));
+        self
+    }
+
+    /// The URLs requested so far, in order
+    pub fn requested(&self) -> Vec<String> {
+        self.requests
+            .borrow()
+            .iter()
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+
+    /// The headers sent with the requests so far
+    pub fn headers(&self) -> Vec<Vec<Header>> {
+        self.requests
+            .borrow()
+            .iter()
+            .map(|(_, headers)| headers.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl HttpClient for Mock {
+    fn stream(
+        &self,
+        url: &str,
+        headers: &[Header],
+        body: &mut dyn Body,
+    ) -> Result<Response, Error> {
+        self.requests
+            .borrow_mut()
+            .push((url.to_string(), headers.to_vec()));
+        let network = |message: &str| Error::Network {
+            url: url.to_string(),
+            message: message.to_string(),
+        };
+        if self.offline {
+            return Err(network("the network is unreachable"));
+        }
+
+        let path = url.split('?').next().unwrap_or(url);
+        let mut response = self
+            .routes
+            .iter()
+            .find(|(route, _)| route == url)
+            .or_else(|| self.routes.iter().find(|(route, _)| route == path))
+            .map(|(_, response)| response.clone())
+            .unwrap_or(Response {
+                status: 404,
+                body: br#"{"message": "Not Found"}"#.to_vec(),
+                ..Response::default()
+            });
+
+        // Send the rest of a file when asked for a range of it, as servers
+        // do, unless it has changed since the validator in If-Range
+        let header = |name| headers.iter().find(|(header, _)| *header == name);
+        let unchanged = header("If-Range").is_none_or(|(_, validator)| {
+            response.header("ETag") == Some(validator.as_str())
+                || response.header("Last-Modified") == Some(validator.as_str())
+        });
+        let range = header("Range")
+            .filter(|_| unchanged)
+            .and_then(|(_, range)| range.strip_prefix("bytes=")?.strip_suffix('-'))
+            .and_then(|start| start.parse::<usize>().ok());
+        if let (200, Some(start)) = (response.status, range) {
+            let length = response.body.len();
+            response = if start < length {
+                Response {
+                    status: 206,
+                    body: response.body.split_off(start),
+                    ..response
+                }
+                .with_header(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{}", start, length - 1, length),
+                )
+            } else {
+                Response {
+                    status: 416,
+                    ..Response::default()
+                }
+            };
+        }
+
+        let data = std::mem::take(&mut response.body);
+        let response = response.with_header("Content-Length", &data.len().to_string());
+        if body.start(&response)? {
+            let mut interruptions = self.interruptions.borrow_mut();
+            match interruptions.iter().position(|(route, _)| route == url) {
+                Some(index) => {
+                    let (_, after) = interruptions.remove(index);
+                    body.write(&data[..after.min(data.len())])?;
+                    return Err(network("the connection was reset"));
+                }
+                None => body.write(&data)?,
+            }
+        }
+        Ok(response)
+    }
+}
@@ -0,0 +1,146 @@
+//! Project lock file recording which template a project was generated from
+//!
+//! The lock file is the baseline for upgrades and drift detection: it names
+//! the template source and release, and holds a hash of every file as it was
+//! generated.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::cli::config::TemplateConfig;
+use crate::cli::error::Error;
+use crate::cli::render::Variables;
+
+/// Name of the lock file in the project root
+pub const FILE_NAME: &str = "leap.lock";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProjectLock {
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TemplateLock {
+    /// Where the template came from, e.g. `daniel-samson/leap-project-template`,
+    /// a URL or a path
+    #[serde(default)]
+    pub source: String,
+    /// The tag the template was released as
+    #[serde(default)]
+    pub tag: String,
+    pub version: Version,
+    /// The commit the tag points at
+    #[serde(default)]
+    pub sha: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Lock {
+    pub project: ProjectLock,
+    pub template: TemplateLock,
+    /// The variables the template was rendered with
+    #[serde(default)]
+    pub variables: Variables,
+    /// Content hash of every generated file, keyed by its path relative to the project
+    #[serde(default)]
+    pub files: BTreeMap<String, String>,
+}
+
+impl Lock {
+    pub fn new(name: &str, template: &TemplateConfig) -> Lock {
+        Lock {
+            project: ProjectLock {
+                name: name.to_string(),
+            },
+            template: TemplateLock::from(template),
+            variables: Variables::new(),
+            files: BTreeMap::new(),
+        }
+    }
+
+    /// Record the variables the template is rendered with
+    pub fn with_variables(mut self, variables: Variables) -> Lock {
+        self.variables = variables;
+        self
+    }
+
+    /// Record the generated contents of a file
+    pub fn with_file(mut self, relative: &Path, contents: &[u8]) -> Lock {
+        self.files.insert(key(relative), hash(contents));
+        self
+    }
+}
+
+impl From<&TemplateConfig> for TemplateLock {
+    fn from(template: &TemplateConfig) -> Self {
+        TemplateLock {
+            source: template.source.clone(),
+            tag: template.tag.clone(),
+            version: template.version.clone(),
+            sha: template.sha.clone(),
+        }
+    }
+}
+
+/// The content hash recorded for a file
+pub fn hash(contents: &[u8]) -> String {
+    let digest = Sha256::digest(contents);
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("sha256:{}", hex)
+}
+
+/// Paths are recorded with forward slashes so lock files are portable
+fn key(relative: &Path) -> String {
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Read the lock file of the project at `root`
+pub fn read(root: &Path) -> Result<Lock, Error> {
+    let path = root.join(FILE_NAME);
+    let toml_string = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+    toml::from_str(toml_string.as_str())
+        .map_err(|e| Error::Project(format!("invalid {} because {}", FILE_NAME, e)))
+}
+
+/// Write the lock file of the project at `root`
+pub fn write(root: &Path, lock: &Lock) -> Result<(), Error> {
+    let toml_string = toml::to_string(lock)
+        .map_err(|e| Error::Project(format!("unable to write {} because {}", FILE_NAME, e)))?;
+    let path = root.join(FILE_NAME);
+    std::fs::write(
+        &path,
+        format!(
+            "# This file is generated by leap, it records the template the project was generated from.\n{}",
+            toml_string
+        ),
+    )
+    .map_err(|e| Error::io(&path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lock_works() {
+        let lock = Lock::new("app", &TemplateConfig::default())
+            .with_file(&Path::new("src").join("main.rs"), b"fn main() {}\n");
+        let toml_string = toml::to_string(&lock).unwrap();
+        let read: Lock = toml::from_str(&toml_string).unwrap();
+
+        assert_eq!(read.project.name, "app");
+        assert_eq!(read.template.version, TemplateConfig::default().version);
+        assert_eq!(
+            read.files["src/main.rs"],
+            "sha256:536e506bb90914c243a12b397b9a998f85ae2cbd9ba02dfd03a9e155ca5ca0f4"
+        );
+    }
+}
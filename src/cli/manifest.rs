@@ -0,0 +1,557 @@
+//! Template manifest (`leap-template.toml`)
+//!
+//! A template may declare the variables it needs, optional features and
+//! commands to run once a project has been generated:
+//!
+//! ```toml
+//! [[variables]]
+//! name = "description"
+//! type = "string"
+//! default = "A leap application"
+//! description = "A short description of the project"
+//!
+//! [[features]]
+//! name = "database"
+//! description = "Connect to a database"
+//! default = false
+//! include = ["src/db/**", "migrations/**"]
+//! exclude = ["src/store/memory.rs"]
+//!
+//! [[hooks.post_generate]]
+//! command = "cargo fmt"
+//! when = "database"
+//! ```
+//!
+//! Variables can be `string`, `bool`, `integer` or `choice` (one of
+//! `choices`). Features are booleans, so both can be used in templates.
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::Path;
+
+use glob::{MatchOptions, Pattern};
+use serde::Deserialize;
+
+use crate::cli::error::Error;
+use crate::cli::output;
+use crate::cli::render::{Value, Variables};
+
+/// Name of the manifest in the root of a template
+pub const FILE_NAME: &str = "leap-template.toml";
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    String,
+    Bool,
+    Integer,
+    Choice,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Variable {
+    pub name: String,
+    #[serde(rename = "type", default = "default_kind")]
+    pub kind: Kind,
+    #[serde(default)]
+    pub description: String,
+    pub default: Option<toml::Value>,
+    #[serde(default)]
+    pub choices: Vec<String>,
+}
+
+fn default_kind() -> Kind {
+    Kind::String
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Feature {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub default: bool,
+    /// Files which are only generated when the feature is enabled
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Files which are left out when the feature is enabled
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Hook {
+    /// A shell command, run in the project directory
+    pub command: String,
+    /// Only run the hook when this variable or feature is set
+    pub when: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Hooks {
+    #[serde(default)]
+    pub post_generate: Vec<Hook>,
+}
+
+/// Whether to run the hooks of a template
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HookPolicy {
+    /// Ask first, unless the template is the leap project template. Hooks
+    /// are skipped when there is nobody to ask.
+    #[default]
+    Ask,
+    /// Run them without asking, `--allow-hooks`
+    Allow,
+    /// Never run them, `--no-hooks`
+    Skip,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Manifest {
+    #[serde(default)]
+    pub variables: Vec<Variable>,
+    #[serde(default)]
+    pub features: Vec<Feature>,
+    #[serde(default)]
+    pub hooks: Hooks,
+}
+
+/// Read the manifest of the template at `template`, templates without one get an empty manifest
+pub fn read(template: &Path) -> Result<Manifest, Error> {
+    let path = template.join(FILE_NAME);
+    if !path.exists() {
+        return Ok(Manifest::default());
+    }
+
+    let toml_string = std::fs::read_to_string(&path).map_err(|e| Error::io(&path, e))?;
+    let invalid = |e: String| Error::Template(format!("invalid {} because {}", FILE_NAME, e));
+    let manifest: Manifest = toml::from_str(&toml_string).map_err(|e| invalid(e.to_string()))?;
+    manifest.validate().map_err(invalid)?;
+    Ok(manifest)
+}
+
+impl Manifest {
+    /// Check the manifest makes sense before asking for any values
+    fn validate(&self) -> Result<(), String> {
+        for variable in &self.variables {
+            if variable.kind == Kind::Choice && variable.choices.is_empty() {
+                return Err(format!("variable '{}' has no choices", variable.name));
+            }
+            if let Some(default) = &variable.default {
+                variable.parse(&plain(default))?;
+            }
+        }
+        for feature in &self.features {
+            for pattern in feature.include.iter().chain(feature.exclude.iter()) {
+                Pattern::new(pattern).map_err(|e| {
+                    format!(
+                        "feature '{}' has an invalid pattern '{}': {}",
+                        feature.name, pattern, e
+                    )
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in `variables` from `defines`, prompting for anything else when
+    /// `interactive` or falling back to defaults.
+    pub fn resolve(
+        &self,
+        variables: &mut Variables,
+        defines: &[(String, String)],
+        interactive: bool,
+    ) -> Result<(), Error> {
+        self.fill(variables, defines, interactive)
+            .map_err(Error::Usage)
+    }
+
+    fn fill(
+        &self,
+        variables: &mut Variables,
+        defines: &[(String, String)],
+        interactive: bool,
+    ) -> Result<(), String> {
+        for (key, _) in defines {
+            let declared = self.variables.iter().any(|v| &v.name == key)
+                || self.features.iter().any(|f| &f.name == key)
+                || variables.contains_key(key);
+            if !declared {
+                return Err(format!("the template has no variable called '{}'", key));
+            }
+        }
+        let define = |name: &str| {
+            defines
+                .iter()
+                .rev()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v)
+        };
+
+        for variable in &self.variables {
+            let value = match define(&variable.name) {
+                Some(value) => variable.parse(value)?,
+                None if variables.contains_key(&variable.name) => continue,
+                None if interactive => variable.prompt()?,
+                None => match &variable.default {
+                    Some(default) => variable.parse(&plain(default))?,
+                    None => {
+                        return Err(format!(
+                            "a value is needed for '{}', use --define {}=<value>",
+                            variable.name, variable.name
+                        ))
+                    }
+                },
+            };
+            variables.insert(variable.name.clone(), value);
+        }
+
+        for feature in &self.features {
+            let enabled = match define(&feature.name) {
+                Some(value) => parse_bool(value)
+                    .ok_or_else(|| format!("'{}' must be true or false", feature.name))?,
+                None if variables.contains_key(&feature.name) => continue,
+                None if interactive => feature.prompt()?,
+                None => feature.default,
+            };
+            variables.insert(feature.name.clone(), Value::Bool(enabled));
+        }
+
+        for (key, value) in defines {
+            if !self.variables.iter().any(|v| &v.name == key)
+                && !self.features.iter().any(|f| &f.name == key)
+            {
+                variables.insert(key.clone(), Value::Text(value.clone()));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Should the template file at `relative` be generated?
+    pub fn includes(&self, relative: &Path, variables: &Variables) -> bool {
+        if relative == Path::new(FILE_NAME) {
+            return false;
+        }
+
+        let path = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..MatchOptions::new()
+        };
+        let matches = |patterns: &[String]| {
+            patterns.iter().any(|pattern| {
+                Pattern::new(pattern).is_ok_and(|pattern| pattern.matches_with(&path, options))
+            })
+        };
+
+        self.features.iter().all(|feature| {
+            let enabled = variables.get(&feature.name) == Some(&Value::Bool(true));
+            if enabled {
+                !matches(&feature.exclude)
+            } else {
+                !matches(&feature.include)
+            }
+        })
+    }
+
+    /// Run the post generate hooks in the project directory, if `policy`
+    /// allows. Hooks of a `trusted` template run without asking.
+    pub fn run_hooks(
+        &self,
+        project: &Path,
+        variables: &Variables,
+        policy: HookPolicy,
+        trusted: bool,
+    ) -> Result<(), Error> {
+        let hooks: Vec<&Hook> = self
+            .hooks
+            .post_generate
+            .iter()
+            .filter(|hook| match &hook.when {
+                Some(when) => variables.get(when).is_some_and(Value::truthy),
+                None => true,
+            })
+            .collect();
+        if hooks.is_empty() || !allowed(&hooks, policy, trusted, interactive())? {
+            return Ok(());
+        }
+
+        for hook in hooks {
+            say!("Running {}", hook.command);
+            let failed =
+                |message: String| Error::Template(format!("'{}' failed {}", hook.command, message));
+            let mut command = if cfg!(windows) {
+                let mut command = std::process::Command::new("cmd");
+                command.arg("/C");
+                command
+            } else {
+                let mut command = std::process::Command::new("sh");
+                command.arg("-c");
+                command
+            };
+            command.arg(&hook.command).current_dir(project);
+            if output::json() {
+                // Keep stdout for the JSON result
+                command.stdout(std::io::stderr());
+            }
+            let status = command.status();
+            match status {
+                Ok(status) if !status.success() => return Err(failed(format!("with {}", status))),
+                Err(e) => return Err(failed(format!("because {}", e))),
+                Ok(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// May the template run `hooks`? A template from elsewhere could run anything,
+/// so the commands are shown and the user has to agree to them.
+fn allowed(
+    hooks: &[&Hook],
+    policy: HookPolicy,
+    trusted: bool,
+    interactive: bool,
+) -> Result<bool, Error> {
+    let commands: Vec<&str> = hooks.iter().map(|hook| hook.command.as_str()).collect();
+    match policy {
+        HookPolicy::Allow => Ok(true),
+        HookPolicy::Skip => {
+            say!("Skipping the template's commands: {}", commands.join(", "));
+            Ok(false)
+        }
+        HookPolicy::Ask if trusted => Ok(true),
+        HookPolicy::Ask if interactive => {
+            println!("The template wants to run these commands in the project:");
+            for command in &commands {
+                println!("  {}", command);
+            }
+            loop {
+                let answer = ask("Run them? [y/N]").map_err(Error::Usage)?;
+                if answer.is_empty() {
+                    return Ok(false);
+                }
+                match parse_bool(&answer) {
+                    Some(run) => return Ok(run),
+                    None => eprintln!("Please answer yes or no"),
+                }
+            }
+        }
+        HookPolicy::Ask => {
+            log::warn!(
+                "skipped the template's commands {}, run them yourself or pass --allow-hooks",
+                commands.join(", ")
+            );
+            Ok(false)
+        }
+    }
+}
+
+impl Variable {
+    /// Check and convert a value given on the command line or at a prompt
+    fn parse(&self, value: &str) -> Result<Value, String> {
+        match self.kind {
+            Kind::String => Ok(Value::Text(value.to_string())),
+            Kind::Bool => parse_bool(value)
+                .map(Value::Bool)
+                .ok_or_else(|| format!("'{}' must be true or false", self.name)),
+            Kind::Integer => value
+                .trim()
+                .parse()
+                .map(Value::Integer)
+                .map_err(|_| format!("'{}' must be a whole number", self.name)),
+            Kind::Choice => {
+                if self.choices.iter().any(|choice| choice == value) {
+                    Ok(Value::Text(value.to_string()))
+                } else {
+                    Err(format!(
+                        "'{}' must be one of {}",
+                        self.name,
+                        self.choices.join(", ")
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Ask for a value until a valid one is given
+    fn prompt(&self) -> Result<Value, String> {
+        let default = self.default.as_ref().map(plain);
+        let mut question = if self.description.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, self.description)
+        };
+        if self.kind == Kind::Choice {
+            question.push_str(&format!(" [{}]", self.choices.join("/")));
+        }
+        if let Some(default) = &default {
+            question.push_str(&format!(" [default: {}]", default));
+        }
+
+        loop {
+            let answer = ask(&question)?;
+            let answer = match (&default, answer.is_empty()) {
+                (Some(default), true) => default.clone(),
+                (None, true) => continue,
+                _ => answer,
+            };
+            match self.parse(&answer) {
+                Ok(value) => return Ok(value),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+}
+
+impl Feature {
+    /// Ask whether to enable the feature
+    fn prompt(&self) -> Result<bool, String> {
+        let question = format!(
+            "Enable {}{}? [{}]",
+            self.name,
+            if self.description.is_empty() {
+                String::new()
+            } else {
+                format!(" ({})", self.description)
+            },
+            if self.default { "Y/n" } else { "y/N" }
+        );
+        loop {
+            let answer = ask(&question)?;
+            if answer.is_empty() {
+                return Ok(self.default);
+            }
+            match parse_bool(&answer) {
+                Some(enabled) => return Ok(enabled),
+                None => eprintln!("Please answer yes or no"),
+            }
+        }
+    }
+}
+
+/// Can we ask the user for values? Not when the answer is expected as JSON
+pub fn interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal() && !output::json()
+}
+
+fn ask(question: &str) -> Result<String, String> {
+    print!("{}: ", question);
+    std::io::stdout().flush().map_err(|e| e.to_string())?;
+    let mut answer = String::new();
+    let read = std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| e.to_string())?;
+    if read == 0 {
+        return Err("no answer given".to_string());
+    }
+    Ok(answer.trim().to_string())
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" | "on" => Some(true),
+        "false" | "no" | "n" | "0" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// A toml value as it would be typed in
+fn plain(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+
+    const MANIFEST: &str = r#"
+[[variables]]
+name = "port"
+type = "integer"
+default = 8080
+
+[[variables]]
+name = "style"
+type = "choice"
+choices = ["api", "site"]
+
+[[features]]
+name = "database"
+include = ["src/db/**"]
+exclude = ["src/store.rs"]
+"#;
+
+    #[test]
+    fn resolve_works() {
+        let manifest: Manifest = toml::from_str(MANIFEST).unwrap();
+        manifest.validate().unwrap();
+
+        let mut variables = Variables::new();
+        let defines = vec![("style".to_string(), "api".to_string())];
+        manifest.resolve(&mut variables, &defines, false).unwrap();
+        assert_eq!(variables["port"], Value::Integer(8080));
+        assert_eq!(variables["style"], Value::Text("api".to_string()));
+        assert_eq!(variables["database"], Value::Bool(false));
+
+        let mut variables = Variables::new();
+        assert!(manifest.resolve(&mut variables, &[], false).is_err());
+        let defines = vec![("style".to_string(), "cli".to_string())];
+        assert!(manifest.resolve(&mut variables, &defines, false).is_err());
+        let defines = vec![("colour".to_string(), "red".to_string())];
+        assert!(manifest.resolve(&mut variables, &defines, false).is_err());
+    }
+
+    #[test]
+    fn hooks_need_consent() {
+        let manifest: Manifest = toml::from_str(
+            r#"
+[[hooks.post_generate]]
+command = "echo ran > ran.txt"
+"#,
+        )
+        .unwrap();
+        let hooks: Vec<&Hook> = manifest.hooks.post_generate.iter().collect();
+        assert!(!allowed(&hooks, HookPolicy::Ask, false, false).unwrap());
+        assert!(!allowed(&hooks, HookPolicy::Skip, true, true).unwrap());
+        assert!(allowed(&hooks, HookPolicy::Ask, true, false).unwrap());
+        assert!(allowed(&hooks, HookPolicy::Allow, false, false).unwrap());
+
+        let project = scratch("hooks");
+        std::fs::create_dir_all(&project).unwrap();
+        let variables = Variables::new();
+        // Tests have no terminal to ask on
+        manifest
+            .run_hooks(&project, &variables, HookPolicy::Ask, false)
+            .unwrap();
+        assert!(!project.join("ran.txt").exists());
+        manifest
+            .run_hooks(&project, &variables, HookPolicy::Allow, false)
+            .unwrap();
+        assert!(project.join("ran.txt").exists());
+    }
+
+    #[test]
+    fn includes_works() {
+        let manifest: Manifest = toml::from_str(MANIFEST).unwrap();
+        let mut variables = Variables::new();
+        variables.insert("database".to_string(), Value::Bool(false));
+
+        assert!(!manifest.includes(Path::new(FILE_NAME), &variables));
+        assert!(!manifest.includes(&Path::new("src/db").join("mod.rs"), &variables));
+        assert!(manifest.includes(Path::new("src/store.rs"), &variables));
+
+        variables.insert("database".to_string(), Value::Bool(true));
+        assert!(manifest.includes(&Path::new("src/db").join("mod.rs"), &variables));
+        assert!(!manifest.includes(Path::new("src/store.rs"), &variables));
+    }
+}
@@ -1,39 +1,483 @@
+#[macro_use]
+mod output;
+
+mod cache;
+mod cargo;
 #[allow(dead_code)]
 #[allow(unused)]
 mod config;
 mod download;
+mod error;
+mod extract;
 mod fs;
 mod github;
+mod http;
+mod lock;
+mod manifest;
+mod project;
+mod reload;
+mod render;
+mod serve;
+mod source;
+mod tar;
 mod template;
+mod update;
+mod upgrade;
+mod verify;
+mod watch;
 mod zip;
 
-pub fn update_cli() {
+pub use cache::CacheCommand;
+pub use config::ConfigCommand;
+pub use error::Error;
+pub use github::{version_requirement, PROJECT_TEMPLATE_REPOSITORY};
+pub use manifest::HookPolicy;
+pub use output::OutputOptions;
+pub use serve::ServeOptions;
+pub use source::Source;
+pub use template::NewOptions;
+pub use update::UpdateOptions;
+pub use upgrade::UpgradeOptions;
+
+/// Apply the global command line flags
+pub fn init(options: OutputOptions, config: Option<std::path::PathBuf>) {
+    output::init(options);
+    if let Some(path) = config {
+        config::set_path(path);
+    }
+}
+
+/// Report why a sub-command failed, as JSON with `--json`
+pub fn report_error(e: &Error) {
+    if output::json() {
+        output::print_error(e);
+    } else if matches!(e, Error::Interrupted) {
+        // The user knows
+    } else if output::color() {
+        eprintln!("\x1b[1;31merror\x1b[0m: {}", e);
+    } else {
+        eprintln!("error: {}", e);
+    }
+}
+
+pub fn update_cli(options: UpdateOptions) -> Result<(), Error> {
     // Check for updates
-    template::update();
-    println!("You are currently are up to date")
+    if !options.check {
+        template::update(&http::Surf, true)?;
+    }
+    let update = update::run(&http::Surf, &options)?;
+
+    let (status, path, command) = match update.installed {
+        update::Installed::UpToDate => ("up_to_date", None, None),
+        update::Installed::Available => ("available", None, None),
+        update::Installed::Replaced(path) => ("replaced", Some(path), None),
+        update::Installed::Cargo(command) => ("cargo", None, Some(command)),
+    };
+    let updated = output::Updated {
+        template: output::Template::new(&config::config()?.template, None),
+        leap: output::LeapUpdate {
+            current: update.current.to_string(),
+            latest: update.latest.to_string(),
+            status,
+            path,
+            command,
+            changelog: update
+                .newer
+                .iter()
+                .map(|release| output::ReleaseNotes {
+                    version: release.version.to_string(),
+                    notes: update::summary(&release.notes),
+                })
+                .collect(),
+        },
+    };
+
+    output::print(&updated, |updated| {
+        let leap = &updated.leap;
+        if leap.status == "up_to_date" {
+            println!("leap {} is up to date", leap.current);
+        } else {
+            println!("Changes since leap {}:", leap.current);
+            for release in &leap.changelog {
+                println!();
+                println!("{}", release.version);
+                for line in &release.notes {
+                    println!("  {}", line);
+                }
+            }
+            println!();
+        }
+        match (leap.status, &leap.path, &leap.command) {
+            ("available", _, _) => {
+                println!(
+                    "leap {} is available, run leap update to install it",
+                    leap.latest
+                )
+            }
+            (_, Some(path), _) => println!(
+                "Updated leap from {} to {} in {}",
+                leap.current,
+                leap.latest,
+                path.display()
+            ),
+            (_, _, Some(command)) => {
+                println!(
+                    "leap was installed with cargo, update it to {} with:",
+                    leap.latest
+                );
+                println!("  {}", command);
+            }
+            _ => {}
+        }
+        if let (Some(version), false) = (&updated.template.version, options.check) {
+            say!("The project template {} is up to date", version);
+        }
+    });
+    Ok(())
 }
 
-pub fn new_project(name: String) {
-    println!("Generating {}", name);
+pub fn new_project(mut options: NewOptions) -> Result<(), Error> {
+    say!("Generating {}", options.name);
+    // A template set for the project or in the environment
+    let configured = config::settings()?.new.template;
+    if options.template.is_none() && configured != PROJECT_TEMPLATE_REPOSITORY {
+        let source = configured
+            .parse()
+            .map_err(|e| Error::Config(format!("new.template {}", e)))?;
+        options.template = Some(source);
+    }
     // Check for updates
-    template::update();
+    if options.latest_template() && !options.offline {
+        match template::update(&http::Surf, options.refresh) {
+            // A template which has been tampered with is not used
+            Err(e @ Error::Archive { .. }) => return Err(e),
+            Err(e) => log::warn!("unable to update the project template because {}", e),
+            Ok(()) => {}
+        }
+    }
     // Create project
-    match template::new_project(name.as_str()) {
-        Ok(_) => println!("Project successfully created"),
-        Err(e) => {
-            log::error!("unable to save project template because {}", e.to_string());
+    let generated = template::new_project(&http::Surf, &options)?;
+    output::print(&generated, |_| println!("Project successfully created"));
+    Ok(())
+}
+
+pub fn upgrade_project(options: UpgradeOptions) -> Result<(), Error> {
+    let upgrade = upgrade::run(&http::Surf, &options)?;
+    let up_to_date = upgrade.changes.is_empty() && upgrade.to <= upgrade.from;
+
+    let changes: Vec<output::Changed> = upgrade
+        .changes
+        .into_iter()
+        .map(|(path, change)| {
+            let (change, reason) = match change {
+                upgrade::Change::Added => ("added", None),
+                upgrade::Change::Updated => ("updated", None),
+                upgrade::Change::Removed => ("removed", None),
+                upgrade::Change::Merged => ("merged", None),
+                upgrade::Change::Conflict => ("conflict", None),
+                upgrade::Change::Skipped(reason) => ("skipped", Some(reason)),
+            };
+            output::Changed {
+                path,
+                change,
+                reason,
+            }
+        })
+        .collect();
+    let upgraded = output::Upgraded {
+        from: upgrade.from.to_string(),
+        to: upgrade.to.to_string(),
+        dry_run: options.dry_run,
+        conflicts: changes
+            .iter()
+            .filter(|changed| matches!(changed.change, "conflict" | "skipped"))
+            .count(),
+        changes,
+    };
+
+    output::print(&upgraded, |upgraded| {
+        if up_to_date {
+            say!("The project is up to date with template {}", upgraded.from);
+            return;
+        }
+
+        for changed in &upgraded.changes {
+            let path = changed.path.display();
+            match (changed.change, &changed.reason) {
+                ("added", _) => println!("  added: {}", path),
+                ("merged", _) => println!(" merged: {}", path),
+                ("conflict", _) => println!("CONFLICT: {}", path),
+                ("skipped", Some(reason)) => println!("skipped: {} ({})", path, reason),
+                (change, _) => println!("{}: {}", change, path),
+            }
+        }
+
+        if upgraded.dry_run {
+            say!(
+                "Upgrading from template {} to {} would change {} files",
+                upgraded.from,
+                upgraded.to,
+                upgraded.changes.len()
+            );
+        } else if upgraded.conflicts > 0 {
+            say!(
+                "Upgraded from template {} to {}, {} files need to be resolved by hand",
+                upgraded.from,
+                upgraded.to,
+                upgraded.conflicts
+            );
+        } else {
+            say!(
+                "Project successfully upgraded from template {} to {}",
+                upgraded.from,
+                upgraded.to
+            );
+        }
+    });
+    Ok(())
+}
+
+pub fn serve_project(options: ServeOptions) -> Result<(), Error> {
+    match serve::run(&options) {
+        Err(_) if serve::interrupted() => Err(Error::Interrupted),
+        served => served,
+    }?;
+    output::print(&output::Served {}, |_| {});
+    Ok(())
+}
+
+pub fn cache(command: CacheCommand) -> Result<(), Error> {
+    let cached = |cached: cache::Cached| output::Cached {
+        template: cached
+            .template
+            .map(|template| output::Template::new(&template, None)),
+        paths: cached.paths,
+        size: cached.size,
+        fetched: cached.fetched,
+    };
+
+    match command {
+        CacheCommand::List => {
+            let list = output::CacheList {
+                templates: cache::list()?.into_iter().map(cached).collect(),
+            };
+            output::print(&list, |list| {
+                if list.templates.is_empty() {
+                    println!("No templates are cached");
+                    return;
+                }
+                println!("{:<14} {:<44} {:>10}  FETCHED", "VERSION", "SOURCE", "SIZE");
+                for cached in &list.templates {
+                    let (version, source) = match &cached.template {
+                        Some(template) => (
+                            template.version.as_deref().unwrap_or("-").to_string(),
+                            template.source.clone(),
+                        ),
+                        None => {
+                            let name = cached.paths[0].file_name().unwrap_or_default();
+                            ("-".to_string(), name.to_string_lossy().to_string())
+                        }
+                    };
+                    println!(
+                        "{:<14} {:<44} {:>10}  {}",
+                        version,
+                        source,
+                        cache::human_size(cached.size),
+                        template::date(cached.fetched)
+                    );
+                }
+            });
+        }
+        CacheCommand::Clean { keep } => {
+            let removed: Vec<output::Cached> =
+                cache::clean(keep)?.into_iter().map(cached).collect();
+            let clean = output::CacheClean {
+                freed: removed.iter().map(|cached| cached.size).sum(),
+                removed,
+            };
+            output::print(&clean, |clean| {
+                println!(
+                    "Removed {} cached templates, freeing {}",
+                    clean.removed.len(),
+                    cache::human_size(clean.freed)
+                )
+            });
+        }
+        CacheCommand::Prefetch { version, pre } => {
+            let (template, path) = cache::prefetch(&http::Surf, &version, pre)?;
+            let prefetch = output::CachePrefetch {
+                template: output::Template::new(&template, Some(path.clone())),
+            };
+            output::print(&prefetch, |_| {
+                println!("Cached template {} in {}", template.version, path.display())
+            });
+        }
+        CacheCommand::Path => {
+            let path = output::CachePath {
+                path: cache::dir()?,
+            };
+            output::print(&path, |path| println!("{}", path.path.display()));
+        }
+    }
+    Ok(())
+}
+
+pub fn config(command: ConfigCommand) -> Result<(), Error> {
+    let value = |key: String, value: toml::Value| output::ConfigValue {
+        key,
+        value,
+        origin: None,
+    };
+    let print_value = |value: &output::ConfigValue| match &value.value {
+        toml::Value::String(text) => println!("{}", text),
+        other => println!("{}", other),
+    };
+
+    match command {
+        ConfigCommand::Get { key } => {
+            let got = config::get(&config::settings()?, &key)?;
+            output::print(&value(key, got), print_value);
+        }
+        ConfigCommand::Set { key, value: new } => {
+            let changed = config::set(&config::config()?, &key, &new)?;
+            let set = config::get(&changed, &key)?;
+            config::write(changed)?;
+            overridden(&key)?;
+            output::print(&value(key, set), |value| {
+                say!("{} is now {}", value.key, value.value)
+            });
+        }
+        ConfigCommand::Unset { key } => {
+            let changed = config::unset(&config::config()?, &key)?;
+            let default = config::get(&changed, &key)?;
+            config::write(changed)?;
+            overridden(&key)?;
+            output::print(&value(key, default), |value| {
+                say!("{} is back to {}", value.key, value.value)
+            });
+        }
+        ConfigCommand::List { show_origin } => {
+            let layered = config::layered()?;
+            let list = output::ConfigList {
+                values: config::list(&layered.config)?
+                    .into_iter()
+                    .map(|(key, got)| match got.as_str() {
+                        // Shown with leap config get, but not on every list
+                        Some(secret) if key == "github.token" && !secret.is_empty() => {
+                            (key, toml::Value::String("<hidden>".to_string()))
+                        }
+                        _ => (key, got),
+                    })
+                    .map(|(key, got)| output::ConfigValue {
+                        origin: layered
+                            .origins
+                            .get(&key)
+                            .filter(|_| show_origin)
+                            .map(ToString::to_string),
+                        ..value(key, got)
+                    })
+                    .collect(),
+            };
+            output::print(&list, |list| {
+                for value in &list.values {
+                    match &value.origin {
+                        Some(origin) => println!("{}\t{} = {}", origin, value.key, value.value),
+                        None => println!("{} = {}", value.key, value.value),
+                    }
+                }
+            });
         }
+        ConfigCommand::Path => {
+            let path = output::ConfigPath {
+                path: config::path()?,
+            };
+            output::print(&path, |path| println!("{}", path.path.display()));
+        }
+        ConfigCommand::Edit => {
+            config::edit()?;
+            output::print(&output::ConfigEdited {}, |_| {});
+        }
+        ConfigCommand::Migrate { dry_run } => {
+            let migration = config::migrate(dry_run)?;
+            let migrated = output::ConfigMigrated {
+                from: migration.from,
+                to: config::SCHEMA_VERSION,
+                dry_run,
+                changes: migration
+                    .changes
+                    .into_iter()
+                    .map(|(key, changed)| value(key, changed))
+                    .collect(),
+                backup: migration.backup,
+            };
+            output::print(&migrated, |migrated| {
+                if migrated.from == migrated.to {
+                    println!("The config is up to date (schema version {})", migrated.to);
+                    return;
+                }
+                for changed in &migrated.changes {
+                    println!("{} = {}", changed.key, changed.value);
+                }
+                match &migrated.backup {
+                    Some(backup) => println!(
+                        "Migrated the config from schema version {} to {}, the old config is in {}",
+                        migrated.from,
+                        migrated.to,
+                        backup.display()
+                    ),
+                    None => println!(
+                        "Would migrate the config from schema version {} to {}",
+                        migrated.from, migrated.to
+                    ),
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Warn when a key changed in the global config is set by a project or the environment
+fn overridden(key: &str) -> Result<(), Error> {
+    let layered = config::layered()?;
+    if let Some(origin @ (config::Origin::Project(_) | config::Origin::Env(_))) =
+        layered.origins.get(key)
+    {
+        log::warn!("{} is overridden by {}", key, origin);
     }
+    Ok(())
 }
 
-pub fn upgrade_project() {
-    todo!("upgrade existing project");
+/// Print the script completing leap commands in `shell`
+pub fn completions(shell: &str, script: String) -> Result<(), Error> {
+    let completions = output::Completions {
+        shell: shell.to_string(),
+        script,
+    };
+    output::print(&completions, |completions| print!("{}", completions.script));
+    Ok(())
 }
 
-pub fn serve_project() {
-    todo!("start serving existing project");
+/// The releases in the template cache as `(source, version)`, newest first
+pub fn cached_releases() -> Result<Vec<(String, String)>, Error> {
+    let mut releases: Vec<_> = cache::list()?
+        .into_iter()
+        .filter_map(|cached| cached.template)
+        .filter(|template| !template.tag.is_empty())
+        .map(|template| (template.source, template.version))
+        .collect();
+    releases.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    releases.dedup();
+    Ok(releases
+        .into_iter()
+        .map(|(source, version)| (source, version.to_string()))
+        .collect())
 }
 
-pub fn watch_project() {
-    todo!("start serving existing project, watch for changes and reload resources");
+pub fn watch_project(options: ServeOptions) -> Result<(), Error> {
+    match watch::run(&options) {
+        Err(_) if serve::interrupted() => Err(Error::Interrupted),
+        watched => watched,
+    }?;
+    output::print(&output::Served {}, |_| {});
+    Ok(())
 }
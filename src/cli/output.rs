@@ -0,0 +1,287 @@
+//! How leap talks to the terminal
+//!
+//! The global command line flags are set once at start up. Status messages
+//! go through `say!`, which keeps quiet with `--quiet` or `--json`, while
+//! results such as `leap cache list` are always printed.
+//!
+//! With `--json` every sub-command prints a single JSON object to stdout:
+//!
+//! ```json
+//! {"schema_version": 1, "ok": true, "data": {...}}
+//! {"schema_version": 1, "ok": false, "error": {"kind": "network", "message": "...", "exit_code": 5}}
+//! ```
+//!
+//! The types below are that schema. Fields may be added without changing
+//! [`SCHEMA_VERSION`], it is raised when a field is renamed, removed or
+//! changes meaning.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::Serialize;
+
+use crate::cli::config::TemplateConfig;
+use crate::cli::error::Error;
+
+/// Version of the JSON printed with `--json`
+pub const SCHEMA_VERSION: u32 = 1;
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+static JSON: AtomicBool = AtomicBool::new(false);
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Settings from the global command line flags
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OutputOptions {
+    /// Only print results and errors
+    pub quiet: bool,
+    /// Print results as JSON
+    pub json: bool,
+    /// Use colors on the terminal
+    pub color: bool,
+}
+
+/// Apply the global command line flags
+pub fn init(options: OutputOptions) {
+    QUIET.store(options.quiet, Ordering::SeqCst);
+    JSON.store(options.json, Ordering::SeqCst);
+    COLOR.store(options.color, Ordering::SeqCst);
+}
+
+/// Are status messages left out?
+pub fn quiet() -> bool {
+    QUIET.load(Ordering::SeqCst) || json()
+}
+
+/// Are results printed as JSON?
+pub fn json() -> bool {
+    JSON.load(Ordering::SeqCst)
+}
+
+/// Are colors used on the terminal?
+pub fn color() -> bool {
+    COLOR.load(Ordering::SeqCst)
+}
+
+/// Print a status message, unless asked to keep quiet
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if !$crate::cli::output::quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Print the result of a sub-command, as JSON or for people
+pub fn print<T: Serialize>(data: &T, human: impl FnOnce(&T)) {
+    if !json() {
+        human(data);
+        return;
+    }
+    let printed = serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "ok": true,
+        "data": data,
+    });
+    println!("{}", printed);
+}
+
+/// Print why a sub-command failed as JSON
+pub fn print_error(e: &Error) {
+    let mut error = serde_json::json!({
+        "kind": e.kind(),
+        "message": e.to_string(),
+        "exit_code": e.exit_code(),
+    });
+    match e {
+        Error::Io { path, .. } | Error::Archive { path, .. } if !path.as_os_str().is_empty() => {
+            error["path"] = path.display().to_string().into();
+        }
+        Error::Network { url, .. } => error["url"] = url.clone().into(),
+        Error::GitHubApi { url, status, .. } => {
+            error["url"] = url.clone().into();
+            error["status"] = (*status).into();
+        }
+        _ => {}
+    }
+    let printed = serde_json::json!({
+        "schema_version": SCHEMA_VERSION,
+        "ok": false,
+        "error": error,
+    });
+    println!("{}", printed);
+}
+
+/// A template a project is generated from
+#[derive(Serialize, Debug, Clone)]
+pub struct Template {
+    /// The repository, URL or path the template came from
+    pub source: String,
+    /// The released version, `None` for templates which are not releases
+    pub version: Option<String>,
+    pub tag: Option<String>,
+    /// Where the template's files are
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl Template {
+    pub fn new(template: &TemplateConfig, path: Option<PathBuf>) -> Template {
+        let released = !template.tag.is_empty();
+        Template {
+            source: template.source.clone(),
+            version: Some(template.version.to_string()).filter(|_| released),
+            tag: Some(template.tag.clone()).filter(|_| released),
+            path,
+        }
+    }
+}
+
+/// `leap new`
+#[derive(Serialize, Debug)]
+pub struct Generated {
+    pub name: String,
+    pub path: PathBuf,
+    pub template: Template,
+}
+
+/// `leap update`
+#[derive(Serialize, Debug)]
+pub struct Updated {
+    /// The latest project template
+    pub template: Template,
+    pub leap: LeapUpdate,
+}
+
+/// How `leap update` updated leap itself
+#[derive(Serialize, Debug)]
+pub struct LeapUpdate {
+    /// The version which ran `leap update`
+    pub current: String,
+    pub latest: String,
+    /// up_to_date, available, replaced or cargo
+    pub status: &'static str,
+    /// The binary which was replaced
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// The command updating leap, when it was installed with cargo
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<String>,
+    /// The releases newer than the current version, newest first
+    pub changelog: Vec<ReleaseNotes>,
+}
+
+/// The start of a release's notes
+#[derive(Serialize, Debug)]
+pub struct ReleaseNotes {
+    pub version: String,
+    pub notes: Vec<String>,
+}
+
+/// A file changed by `leap upgrade`
+#[derive(Serialize, Debug)]
+pub struct Changed {
+    pub path: PathBuf,
+    /// added, updated, removed, merged, conflict or skipped
+    pub change: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// `leap upgrade`
+#[derive(Serialize, Debug)]
+pub struct Upgraded {
+    pub from: String,
+    pub to: String,
+    pub dry_run: bool,
+    pub changes: Vec<Changed>,
+    /// Files which need to be resolved by hand
+    pub conflicts: usize,
+}
+
+/// `leap serve` and `leap watch`, once the project has stopped
+#[derive(Serialize, Debug)]
+pub struct Served {}
+
+/// A template, or a file no template accounts for, in the cache
+#[derive(Serialize, Debug)]
+pub struct Cached {
+    pub template: Option<Template>,
+    pub paths: Vec<PathBuf>,
+    /// In bytes
+    pub size: u64,
+    /// In seconds since the unix epoch
+    pub fetched: u64,
+}
+
+/// `leap cache list`
+#[derive(Serialize, Debug)]
+pub struct CacheList {
+    pub templates: Vec<Cached>,
+}
+
+/// `leap cache clean`
+#[derive(Serialize, Debug)]
+pub struct CacheClean {
+    pub removed: Vec<Cached>,
+    /// In bytes
+    pub freed: u64,
+}
+
+/// `leap cache prefetch`
+#[derive(Serialize, Debug)]
+pub struct CachePrefetch {
+    pub template: Template,
+}
+
+/// `leap cache path`
+#[derive(Serialize, Debug)]
+pub struct CachePath {
+    pub path: PathBuf,
+}
+
+/// A config key and its value
+#[derive(Serialize, Debug)]
+pub struct ConfigValue {
+    pub key: String,
+    pub value: toml::Value,
+    /// Where the value came from, with `--show-origin`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+}
+
+/// `leap config list`
+#[derive(Serialize, Debug)]
+pub struct ConfigList {
+    pub values: Vec<ConfigValue>,
+}
+
+/// `leap config path`
+#[derive(Serialize, Debug)]
+pub struct ConfigPath {
+    pub path: PathBuf,
+}
+
+/// `leap config migrate`
+#[derive(Serialize, Debug)]
+pub struct ConfigMigrated {
+    /// The schema version the config was written with
+    pub from: u32,
+    pub to: u32,
+    pub dry_run: bool,
+    /// The keys which are added or changed
+    pub changes: Vec<ConfigValue>,
+    /// Where the config was copied to before it was migrated
+    pub backup: Option<PathBuf>,
+}
+
+/// `leap config edit`
+#[derive(Serialize, Debug)]
+pub struct ConfigEdited {}
+
+/// `leap completions`
+#[derive(Serialize, Debug)]
+pub struct Completions {
+    pub shell: String,
+    pub script: String,
+}
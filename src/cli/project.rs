@@ -0,0 +1,52 @@
+//! Leap project discovery
+
+use std::path::{Path, PathBuf};
+
+use crate::cli::error::Error;
+
+/// Find the root of the leap project containing the current directory
+pub fn root() -> Result<PathBuf, Error> {
+    find_root(std::env::current_dir()?)
+}
+
+/// Walk up from `start` until a directory containing a `Cargo.toml` is found
+pub fn find_root<P: AsRef<Path>>(start: P) -> Result<PathBuf, Error> {
+    let mut path = Some(start.as_ref());
+    while let Some(dir) = path {
+        if dir.join("Cargo.toml").is_file() {
+            log::info!("project root: {:?}", dir);
+            return Ok(dir.to_path_buf());
+        }
+        path = dir.parent();
+    }
+
+    Err(Error::Project(
+        "cannot find a leap project (Cargo.toml) in this directory or any parent".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+
+    #[test]
+    fn find_root_works() {
+        let dir = scratch("project-root");
+        let nested = dir.join("app/src/bin");
+        std::fs::create_dir_all(&nested).unwrap();
+        std::fs::write(dir.join("app/Cargo.toml"), "").unwrap();
+        // A directory called Cargo.toml is not a manifest
+        std::fs::create_dir_all(nested.join("Cargo.toml")).unwrap();
+
+        assert_eq!(find_root(&nested).unwrap(), dir.join("app"));
+        assert_eq!(find_root(dir.join("app")).unwrap(), dir.join("app"));
+        // The temporary directory is not in a project, unless it is
+        if std::env::temp_dir()
+            .ancestors()
+            .all(|dir| !dir.join("Cargo.toml").is_file())
+        {
+            assert!(matches!(find_root(&dir), Err(Error::Project(_))));
+        }
+    }
+}
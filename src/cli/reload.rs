@@ -0,0 +1,455 @@
+//! Browser live reload for the watch sub-command
+//!
+//! Leap listens on the address the user asked for and proxies every request
+//! to the application, which runs on a private port. HTML pages get a small
+//! script injected which listens for server-sent events, reloading the page
+//! after a rebuild or swapping stylesheets when only CSS has changed.
+//!
+//! Every connection carries a single request, the proxy closes it once the
+//! response has been sent, so browsers send any further requests on new ones.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The server-sent events endpoint
+const EVENTS_PATH: &str = "/__leap/livereload";
+/// The script injected into HTML pages
+const SCRIPT_PATH: &str = "/__leap/livereload.js";
+const SCRIPT: &str = r#"(function () {
+  var source = new EventSource("/__leap/livereload");
+  source.addEventListener("reload", function () {
+    window.location.reload();
+  });
+  source.addEventListener("css", function () {
+    var links = document.querySelectorAll('link[rel="stylesheet"]');
+    Array.prototype.forEach.call(links, function (link) {
+      var url = new URL(link.href);
+      url.searchParams.set("leap-reload", Date.now());
+      link.href = url.toString();
+    });
+  });
+})();
+"#;
+
+/// A reload proxy sitting in front of the application
+pub(crate) struct LiveReload {
+    upstream: u16,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+}
+
+impl LiveReload {
+    /// Listen on `host:port` and forward requests to a free local port
+    pub fn start(host: &str, port: u16) -> Result<LiveReload, std::io::Error> {
+        let upstream = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let listener = TcpListener::bind((host, port))?;
+        let clients = Arc::new(Mutex::new(Vec::new()));
+
+        let accepted = clients.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::info!("live reload connection failed: {}", e);
+                        continue;
+                    }
+                };
+                let clients = accepted.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = handle(stream, upstream, clients) {
+                        log::info!("live reload request failed: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(LiveReload { upstream, clients })
+    }
+
+    /// The port the application should listen on
+    pub fn upstream(&self) -> u16 {
+        self.upstream
+    }
+
+    /// Wait until the application accepts connections, up to `timeout`
+    pub fn wait_for_upstream(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if TcpStream::connect(("127.0.0.1", self.upstream)).is_ok() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    }
+
+    /// Reload every connected browser
+    pub fn reload(&self) {
+        self.send("reload");
+    }
+
+    /// Ask every connected browser to reload its stylesheets
+    pub fn reload_css(&self) {
+        self.send("css");
+    }
+
+    fn send(&self, event: &str) {
+        let mut clients = match self.clients.lock() {
+            Ok(clients) => clients,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let message = format!("event: {}\ndata: {}\n\n", event, event);
+        clients.retain(|mut client| client.write_all(message.as_bytes()).is_ok());
+        log::info!("sent {} to {} browsers", event, clients.len());
+    }
+}
+
+/// An HTTP request or response head
+struct Head {
+    start: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Head {
+    /// Read a head, leaving the reader positioned at the start of the body
+    fn read<R: BufRead>(reader: &mut R) -> Result<Head, std::io::Error> {
+        let mut start = String::new();
+        reader.read_line(&mut start)?;
+        if start.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "connection closed",
+            ));
+        }
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        Ok(Head {
+            start: start.trim_end().to_string(),
+            headers,
+        })
+    }
+
+    /// The status of a response
+    fn status(&self) -> Option<u16> {
+        self.start.split_whitespace().nth(1)?.parse().ok()
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn remove(&mut self, name: &str) {
+        self.headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.headers.push((name.to_string(), value.to_string()));
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut head = format!("{}\r\n", self.start);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        head.into_bytes()
+    }
+}
+
+/// Serve a single browser connection
+fn handle(
+    stream: TcpStream,
+    upstream: u16,
+    clients: Arc<Mutex<Vec<TcpStream>>>,
+) -> Result<(), std::io::Error> {
+    let mut client = BufReader::new(stream.try_clone()?);
+    let mut request = Head::read(&mut client)?;
+    let mut parts = request.start.split_whitespace();
+    let method = parts.next().unwrap_or("GET").to_string();
+    let path = parts.next().unwrap_or("/").to_string();
+    let mut stream = stream;
+
+    if path == EVENTS_PATH {
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: keep-alive\r\n\r\n",
+        )?;
+        if let Ok(mut clients) = clients.lock() {
+            clients.push(stream);
+        }
+        return Ok(());
+    }
+
+    if path == SCRIPT_PATH {
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/javascript\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n{}",
+            SCRIPT.len(),
+            SCRIPT
+        );
+        return stream.write_all(response.as_bytes());
+    }
+
+    let mut app = match TcpStream::connect(("127.0.0.1", upstream)) {
+        Ok(app) => app,
+        Err(_) => return unavailable(&mut stream),
+    };
+
+    // Keep HTML uncompressed so the script can be injected, and use one
+    // request per connection so responses end when the connection closes
+    let upgrade = request.header("Upgrade").is_some();
+    request.remove("Accept-Encoding");
+    if !upgrade {
+        request.set("Connection", "close");
+    }
+    app.write_all(&request.to_bytes())?;
+
+    // Forward the request body, or the browser side of an upgraded
+    // connection. Only the body, anything after it is another request.
+    let chunked = request
+        .header("Transfer-Encoding")
+        .is_some_and(|t| t.eq_ignore_ascii_case("chunked"));
+    let length = request
+        .header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok())
+        .unwrap_or(0);
+    let mut to_app = app.try_clone()?;
+    std::thread::spawn(move || {
+        if upgrade || chunked {
+            let _ = std::io::copy(&mut client, &mut to_app);
+            let _ = to_app.shutdown(Shutdown::Write);
+        } else {
+            let _ = std::io::copy(&mut client.take(length), &mut to_app);
+        }
+    });
+
+    let mut app = BufReader::new(app);
+    let mut response = Head::read(&mut app)?;
+    if !injectable(&method, &response) {
+        if response.status() != Some(101) {
+            response.set("Connection", "close");
+        }
+        stream.write_all(&response.to_bytes())?;
+        std::io::copy(&mut app, &mut stream)?;
+        return stream.shutdown(Shutdown::Both);
+    }
+
+    let mut body = Vec::new();
+    match response.header("Content-Length").map(|l| l.parse::<u64>()) {
+        Some(Ok(length)) => {
+            app.take(length).read_to_end(&mut body)?;
+        }
+        _ => {
+            app.read_to_end(&mut body)?;
+        }
+    }
+    if response
+        .header("Transfer-Encoding")
+        .is_some_and(|t| t.eq_ignore_ascii_case("chunked"))
+    {
+        body = dechunk(&body)?;
+    }
+
+    let body = inject(body);
+    response.remove("Transfer-Encoding");
+    response.set("Content-Length", &body.len().to_string());
+    response.set("Connection", "close");
+    stream.write_all(&response.to_bytes())?;
+    stream.write_all(&body)?;
+    stream.shutdown(Shutdown::Both)
+}
+
+/// Should the script be injected into `response` to a `method` request?
+/// Only pages are, which have a body leap can read.
+fn injectable(method: &str, response: &Head) -> bool {
+    response.status() == Some(200)
+        && method != "HEAD"
+        && response
+            .header("Content-Type")
+            .is_some_and(|t| t.to_ascii_lowercase().starts_with("text/html"))
+        && response.header("Content-Encoding").is_none()
+        && response.header("Content-Length") != Some("0")
+}
+
+/// Tell the browser the application is restarting, and reload once it is back
+fn unavailable(stream: &mut TcpStream) -> Result<(), std::io::Error> {
+    let body = inject(
+        b"<!DOCTYPE html><html><head><title>Restarting</title></head><body><p>The application is not running yet, this page will reload when it is ready.</p></body></html>"
+            .to_vec(),
+    );
+    let head = format!(
+        "HTTP/1.1 502 Bad Gateway\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(&body)
+}
+
+/// Add the live reload script before the closing body tag
+fn inject(mut body: Vec<u8>) -> Vec<u8> {
+    let script = format!("<script src=\"{}\"></script>", SCRIPT_PATH);
+    let lower = body.to_ascii_lowercase();
+    let position = lower
+        .windows(7)
+        .rposition(|window| window == b"</body>")
+        .unwrap_or(body.len());
+    body.splice(position..position, script.bytes());
+    body
+}
+
+/// Decode a chunked transfer encoded body
+fn dechunk(mut body: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid chunked body");
+    let mut decoded = Vec::new();
+    loop {
+        let end = body
+            .windows(2)
+            .position(|w| w == b"\r\n")
+            .ok_or_else(invalid)?;
+        let size = std::str::from_utf8(&body[..end]).map_err(|_| invalid())?;
+        let size = size.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| invalid())?;
+        body = &body[end + 2..];
+        if size == 0 {
+            return Ok(decoded);
+        }
+        if body.len() < size {
+            return Err(invalid());
+        }
+        decoded.extend_from_slice(&body[..size]);
+        body = body.get(size + 2..).unwrap_or(&[]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inject_works() {
+        let page = inject(b"<html><BODY><p>hi</p></BODY></html>".to_vec());
+        assert_eq!(
+            String::from_utf8(page).unwrap(),
+            "<html><BODY><p>hi</p><script src=\"/__leap/livereload.js\"></script></BODY></html>"
+        );
+
+        let fragment = inject(b"<p>hi</p>".to_vec());
+        assert!(String::from_utf8(fragment).unwrap().ends_with("</script>"));
+    }
+
+    fn head(start: &str, headers: &[(&str, &str)]) -> Head {
+        Head {
+            start: start.to_string(),
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn injectable_works() {
+        let html = [("Content-Type", "text/html; charset=utf-8")];
+        let page = head("HTTP/1.1 200 OK", &html);
+        assert!(injectable("GET", &page));
+        assert!(!injectable("HEAD", &page));
+        assert!(!injectable("GET", &head("HTTP/1.1 204 No Content", &html)));
+        assert!(!injectable(
+            "GET",
+            &head("HTTP/1.1 304 Not Modified", &html)
+        ));
+        assert!(!injectable("GET", &head("HTTP/1.1 404 Not Found", &html)));
+        let empty = head(
+            "HTTP/1.1 200 OK",
+            &[("Content-Type", "text/html"), ("Content-Length", "0")],
+        );
+        assert!(!injectable("GET", &empty));
+        let gzip = head(
+            "HTTP/1.1 200 OK",
+            &[("Content-Type", "text/html"), ("Content-Encoding", "gzip")],
+        );
+        assert!(!injectable("GET", &gzip));
+        let json = head("HTTP/1.1 200 OK", &[("Content-Type", "application/json")]);
+        assert!(!injectable("GET", &json));
+    }
+
+    /// Send `request` through the proxy to an application answering with
+    /// `answer`, returning the response and what else reached the application
+    fn proxy(request: &[u8], answer: &'static str) -> (String, Vec<u8>) {
+        let app = TcpListener::bind("127.0.0.1:0").unwrap();
+        let upstream = app.local_addr().unwrap().port();
+        let application = std::thread::spawn(move || {
+            let (stream, _) = app.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            Head::read(&mut reader).unwrap();
+            (&stream).write_all(answer.as_bytes()).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut rest = Vec::new();
+            reader.read_to_end(&mut rest).unwrap();
+            rest
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut browser = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let proxied = std::thread::spawn(move || {
+            handle(stream, upstream, Arc::new(Mutex::new(Vec::new()))).unwrap()
+        });
+        browser.write_all(request).unwrap();
+        let mut response = String::new();
+        browser.read_to_string(&mut response).unwrap();
+        proxied.join().unwrap();
+        drop(browser);
+        (response, application.join().unwrap())
+    }
+
+    #[test]
+    fn proxy_works() {
+        let page =
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: 13\r\n\r\n<body></body>";
+        let (response, rest) = proxy(b"GET / HTTP/1.1\r\n\r\n", page);
+        assert!(response.contains("Connection: close"));
+        assert!(response.ends_with("<body><script src=\"/__leap/livereload.js\"></script></body>"));
+        assert!(rest.is_empty());
+
+        // The second of pipelined requests is not sent on to the application
+        let pipelined = b"POST /a HTTP/1.1\r\nContent-Length: 2\r\nConnection: keep-alive\r\n\r\nhiGET /b HTTP/1.1\r\n\r\n";
+        let ok = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nConnection: keep-alive\r\nContent-Length: 2\r\n\r\nok";
+        let (response, rest) = proxy(pipelined, ok);
+        assert!(response.contains("Connection: close"));
+        assert!(!response.contains("keep-alive"));
+        assert!(response.ends_with("\r\n\r\nok"));
+        assert_eq!(rest, b"hi");
+
+        let modified = "HTTP/1.1 304 Not Modified\r\nContent-Type: text/html\r\n\r\n";
+        let (response, _) = proxy(b"GET / HTTP/1.1\r\n\r\n", modified);
+        assert!(!response.contains("livereload"));
+        let (response, _) = proxy(b"HEAD / HTTP/1.1\r\n\r\n", page);
+        assert!(!response.contains("livereload"));
+    }
+
+    #[test]
+    fn dechunk_works() {
+        let body = dechunk(b"4\r\nWiki\r\n6;ext=1\r\npedia \r\n0\r\n\r\n").unwrap();
+        assert_eq!(body, b"Wikipedia ");
+        assert!(dechunk(b"zz\r\n").is_err());
+    }
+}
@@ -0,0 +1,466 @@
+//! Template rendering
+//!
+//! Templates are plain files with placeholders:
+//!
+//! - `{{project_name}}` inserts a variable, `{{project_name | snake}}` passes
+//!   it through filters (`snake`, `kebab`, `camel`, `pascal`, `title`,
+//!   `upper` and `lower`).
+//! - `{{#if database}} ... {{else}} ... {{/if}}` and
+//!   `{{#unless database}} ... {{/unless}}` include text conditionally. Tags
+//!   which are alone on a line remove the whole line.
+//!
+//! Anything else between braces, such as the escaped braces of a Rust format
+//! string, is left exactly as it is. So are variables and conditionals the
+//! template does not declare, along with their `{{else}}` and end tags, and
+//! any other blocks such as Handlebars' `{{#each}} ... {{else}} ... {{/each}}`.
+//! A tag which would be the template's is written out as it is when it
+//! follows a backslash, `\{{project_name}}` becomes `{{project_name}}`.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// The value of a template variable
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Value {
+    Bool(bool),
+    Integer(i64),
+    Text(String),
+}
+
+impl Value {
+    /// Is the value set, for conditionals
+    pub fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Integer(i) => *i != 0,
+            Value::Text(s) => !s.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Text(s.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+/// Variables available to a template
+pub type Variables = BTreeMap<String, Value>;
+
+/// Why a template could not be rendered
+#[derive(Debug, PartialEq)]
+pub struct RenderError(pub String);
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Text(String),
+    Variable(String, Vec<String>),
+    If(String, bool),
+    Else,
+    End,
+}
+
+enum Node {
+    Text(String),
+    Variable(String, Vec<String>),
+    If(String, bool, Vec<Node>, Vec<Node>),
+}
+
+/// Render a template with the given variables
+pub fn render(template: &str, variables: &Variables) -> Result<String, RenderError> {
+    let tokens = tokenize(template, variables);
+    let mut tokens = tokens.into_iter();
+    let (nodes, end) = parse(&mut tokens)?;
+    if end.is_some() {
+        return Err(RenderError("unexpected {{else}} or {{/if}}".to_string()));
+    }
+
+    let mut output = String::with_capacity(template.len());
+    write(&nodes, variables, &mut output)?;
+    Ok(output)
+}
+
+/// Does this look like a binary file which should be copied untouched?
+pub fn is_binary(contents: &[u8]) -> bool {
+    let start = &contents[..contents.len().min(8000)];
+    start.contains(&0) || std::str::from_utf8(contents).is_err()
+}
+
+/// Apply a case conversion filter
+pub fn filter(name: &str, value: &str) -> Result<String, RenderError> {
+    let words = words(value);
+    let capitalise = |word: &String| {
+        let mut chars = word.chars();
+        match chars.next() {
+            Some(first) => first.to_uppercase().chain(chars).collect::<String>(),
+            None => String::new(),
+        }
+    };
+
+    Ok(match name {
+        "snake" => words.join("_"),
+        "kebab" => words.join("-"),
+        "pascal" => words.iter().map(capitalise).collect(),
+        "camel" => words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                if i == 0 {
+                    word.clone()
+                } else {
+                    capitalise(word)
+                }
+            })
+            .collect(),
+        "title" => words.iter().map(capitalise).collect::<Vec<_>>().join(" "),
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        _ => return Err(RenderError(format!("unknown filter '{}'", name))),
+    })
+}
+
+/// Split a name into lower case words on punctuation and case changes
+fn words(value: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut previous: Option<char> = None;
+
+    for c in value.chars() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            previous = None;
+            continue;
+        }
+        let boundary = match previous {
+            Some(p) => c.is_uppercase() && (p.is_lowercase() || p.is_numeric()),
+            None => false,
+        };
+        if boundary && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(c.to_lowercase());
+        previous = Some(c);
+    }
+
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Is `name` a valid variable or filter name?
+fn identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Classify the contents of a `{{ }}` pair, `None` when it is not a tag.
+/// Variables and conditionals are only tags for the `variables` of the
+/// template.
+fn tag(inner: &str, variables: &Variables) -> Option<Token> {
+    let inner = inner.trim();
+    if let Some(block) = inner.strip_prefix('#') {
+        let mut parts = block.split_whitespace();
+        let negate = match parts.next() {
+            Some("if") => false,
+            Some("unless") => true,
+            _ => return None,
+        };
+        let name = parts.next()?;
+        if parts.next().is_some() || !variables.contains_key(name) {
+            return None;
+        }
+        return Some(Token::If(name.to_string(), negate));
+    }
+    if inner == "else" {
+        return Some(Token::Else);
+    }
+    if inner == "/if" || inner == "/unless" {
+        return Some(Token::End);
+    }
+
+    let mut parts = inner.split('|').map(str::trim);
+    let name = parts.next()?;
+    let filters: Vec<String> = parts.map(String::from).collect();
+    if !variables.contains_key(name) || !filters.iter().all(|f| identifier(f)) {
+        return None;
+    }
+    Some(Token::Variable(name.to_string(), filters))
+}
+
+fn tokenize(template: &str, variables: &Variables) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+    // Whether the current line has only had whitespace and block tags so far
+    let mut blank_line = true;
+    // The blocks which are open, whether each is one of ours
+    let mut blocks: Vec<bool> = Vec::new();
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start + 2..].find("}}") {
+            Some(end) => start + 2 + end,
+            None => break,
+        };
+        let inner = &rest[start + 2..end];
+        let token = tag(inner, variables);
+        if token.is_some() && rest[..start].ends_with('\\') {
+            // An escaped tag, written out without the backslash
+            text.push_str(&rest[..start - 1]);
+            text.push_str(&rest[start..end + 2]);
+            rest = &rest[end + 2..];
+            continue;
+        }
+        let token = match token {
+            Some(Token::If(name, negate)) => {
+                blocks.push(true);
+                Some(Token::If(name, negate))
+            }
+            // Only the else and end of one of our blocks are ours
+            Some(Token::Else) if blocks.last() == Some(&true) => Some(Token::Else),
+            Some(Token::End) if blocks.last() == Some(&true) => {
+                blocks.pop();
+                Some(Token::End)
+            }
+            Some(Token::Else) | Some(Token::End) | None => {
+                let inner = inner.trim();
+                if inner.starts_with('#') {
+                    blocks.push(false);
+                } else if inner.starts_with('/') && blocks.last() == Some(&false) {
+                    blocks.pop();
+                }
+                None
+            }
+            token => token,
+        };
+        let token = match token {
+            Some(token) => token,
+            None => {
+                text.push_str(&rest[..end + 2]);
+                rest = &rest[end + 2..];
+                continue;
+            }
+        };
+
+        text.push_str(&rest[..start]);
+        rest = &rest[end + 2..];
+
+        // Block tags alone on a line take the whole line with them
+        let line_start = text.rfind('\n').map(|i| i + 1);
+        let before_blank = match line_start {
+            Some(line_start) => text[line_start..].trim().is_empty(),
+            None => blank_line && text.trim().is_empty(),
+        };
+        if matches!(token, Token::Variable(_, _)) {
+            blank_line = false;
+        } else {
+            let line_end = rest.find('\n');
+            let after_blank = rest[..line_end.unwrap_or(rest.len())].trim().is_empty();
+            if before_blank && after_blank {
+                text.truncate(line_start.unwrap_or(0));
+                rest = &rest[line_end.map_or(rest.len(), |i| i + 1)..];
+                blank_line = true;
+            } else {
+                blank_line = before_blank;
+            }
+        }
+
+        if !text.is_empty() {
+            tokens.push(Token::Text(std::mem::take(&mut text)));
+        }
+        tokens.push(token);
+    }
+
+    text.push_str(rest);
+    if !text.is_empty() {
+        tokens.push(Token::Text(text));
+    }
+    tokens
+}
+
+/// Parse tokens until the end of the input or an `else` / end tag, which is returned
+fn parse<I: Iterator<Item = Token>>(
+    tokens: &mut I,
+) -> Result<(Vec<Node>, Option<Token>), RenderError> {
+    let mut nodes = Vec::new();
+    while let Some(token) = tokens.next() {
+        match token {
+            Token::Text(text) => nodes.push(Node::Text(text)),
+            Token::Variable(name, filters) => nodes.push(Node::Variable(name, filters)),
+            Token::If(name, negate) => {
+                let (then, end) = parse(tokens)?;
+                let otherwise = match end {
+                    Some(Token::Else) => match parse(tokens)? {
+                        (otherwise, Some(Token::End)) => otherwise,
+                        _ => {
+                            return Err(RenderError(format!("{{{{#if {}}}}} is not closed", name)))
+                        }
+                    },
+                    Some(Token::End) => Vec::new(),
+                    _ => return Err(RenderError(format!("{{{{#if {}}}}} is not closed", name))),
+                };
+                nodes.push(Node::If(name, negate, then, otherwise));
+            }
+            Token::Else | Token::End => return Ok((nodes, Some(token))),
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn write(nodes: &[Node], variables: &Variables, output: &mut String) -> Result<(), RenderError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Variable(name, filters) => {
+                let mut value = variables
+                    .get(name)
+                    .ok_or_else(|| RenderError(format!("unknown variable '{}'", name)))?
+                    .to_string();
+                for name in filters {
+                    value = filter(name, &value)?;
+                }
+                output.push_str(&value);
+            }
+            Node::If(name, negate, then, otherwise) => {
+                let value = variables
+                    .get(name)
+                    .ok_or_else(|| RenderError(format!("unknown variable '{}'", name)))?;
+                if value.truthy() != *negate {
+                    write(then, variables, output)?;
+                } else {
+                    write(otherwise, variables, output)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variables() -> Variables {
+        let mut variables = Variables::new();
+        variables.insert("project_name".to_string(), "MyApp".into());
+        variables.insert("database".to_string(), true.into());
+        variables.insert("auth".to_string(), false.into());
+        variables
+    }
+
+    #[test]
+    fn render_works() {
+        let variables = variables();
+        assert_eq!(
+            render("# {{ project_name | title }}", &variables).unwrap(),
+            "# My App"
+        );
+        assert_eq!(
+            render("name = \"{{project_name|kebab}}\"", &variables).unwrap(),
+            "name = \"my-app\""
+        );
+        for verbatim in &[
+            "println!(\"{{}} {{unknown}}\", x);",
+            "<p>{{ message | capitalize }}</p>",
+            "{{  unknown|upper }} \\{{unknown}}",
+        ] {
+            assert_eq!(render(verbatim, &variables).unwrap(), *verbatim);
+        }
+        assert_eq!(
+            render("\\{{ project_name }} is {{project_name}}", &variables).unwrap(),
+            "{{ project_name }} is MyApp"
+        );
+        assert_eq!(
+            render("\\{{#if database}}x\\{{/if}}", &variables).unwrap(),
+            "{{#if database}}x{{/if}}"
+        );
+    }
+
+    #[test]
+    fn conditionals_work() {
+        let variables = variables();
+        let template = "a\n{{#if database}}\ndb\n{{else}}\nno db\n{{/if}}\n{{#unless auth}}no auth{{/unless}}\n";
+        assert_eq!(render(template, &variables).unwrap(), "a\ndb\nno auth\n");
+        assert_eq!(
+            render("{{project_name}} {{#if database}}\ndb{{/if}}\n", &variables).unwrap(),
+            "MyApp \ndb\n"
+        );
+        assert!(render("{{#if database}}x", &variables).is_err());
+
+        // Blocks which are not the template's are left for what reads the file
+        for verbatim in &[
+            "{{#if missing}}\nx\n{{else}}\ny\n{{/if}}\n",
+            "x{{/if}} {{else}}",
+            "{{#each items}}\n{{this}}\n{{else}}\nnone\n{{/each}}\n",
+        ] {
+            assert_eq!(render(verbatim, &variables).unwrap(), *verbatim);
+        }
+        assert_eq!(
+            render(
+                "{{#if database}}{{#each t}}{{.}}{{else}}-{{/each}}{{else}}no db{{/if}}",
+                &variables
+            )
+            .unwrap(),
+            "{{#each t}}{{.}}{{else}}-{{/each}}"
+        );
+        assert_eq!(
+            render(
+                "{{#unless auth}}{{#if missing}}{{/if}}{{/unless}}",
+                &variables
+            )
+            .unwrap(),
+            "{{#if missing}}{{/if}}"
+        );
+    }
+
+    #[test]
+    fn filters_work() {
+        assert_eq!(filter("snake", "myHTTPApp-v2").unwrap(), "my_httpapp_v2");
+        assert_eq!(filter("pascal", "my-app").unwrap(), "MyApp");
+        assert_eq!(filter("camel", "my_app").unwrap(), "myApp");
+        assert_eq!(filter("upper", "my-app").unwrap(), "MY-APP");
+        assert!(filter("reverse", "my-app").is_err());
+    }
+
+    #[test]
+    fn is_binary_works() {
+        assert!(!is_binary(b"fn main() {}"));
+        assert!(is_binary(b"\x89PNG\r\n\x1a\n\0\0"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Build and run a leap project
+
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant};
+
+use crate::cli::config::ServeConfig;
+use crate::cli::error::Error;
+use crate::cli::{cargo, config, output, project};
+
+/// Set once the user has pressed Ctrl-C
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Process id of the running application, or 0 when nothing is running
+static CHILD: AtomicU32 = AtomicU32::new(0);
+static HANDLER: Once = Once::new();
+
+/// Options passed through to the application binary
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    pub host: String,
+    pub port: u16,
+    pub environment: String,
+    pub release: bool,
+    /// Refresh browsers after a rebuild when watching
+    pub live_reload: bool,
+    /// Extra arguments given after `--`
+    pub args: Vec<String>,
+}
+
+impl ServeOptions {
+    /// The options set in the config, for the flags which were not given
+    pub fn configured() -> Result<ServeOptions, Error> {
+        let serve = config::settings()?.serve;
+        Ok(ServeOptions {
+            host: serve.host,
+            port: serve.port,
+            environment: serve.environment,
+            ..ServeOptions::default()
+        })
+    }
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        let serve = ServeConfig::default();
+        ServeOptions {
+            host: serve.host,
+            port: serve.port,
+            environment: serve.environment,
+            release: false,
+            live_reload: true,
+            args: Vec::new(),
+        }
+    }
+}
+
+/// Build the project containing the current directory and run it until it exits
+pub fn run(options: &ServeOptions) -> Result<(), Error> {
+    handle_interrupts();
+    let root = project::root()?;
+
+    say!("Building {}", root.display());
+    let build = cargo::build(&root, options.release)?;
+    if interrupted() {
+        return Err(Error::Interrupted);
+    }
+    if !build.success {
+        return Err(Error::Project("the project failed to build".to_string()));
+    }
+    let executable = build
+        .executable
+        .ok_or_else(|| Error::Project("the project does not have a binary target".to_string()))?;
+
+    say!(
+        "Serving on http://{}:{} ({})",
+        options.host,
+        options.port,
+        options.environment
+    );
+    let mut server = Server::start(&root, &executable, options)
+        .map_err(|e| Error::Project(format!("unable to start project because {}", e)))?;
+    let status = server.wait()?;
+    match (interrupted(), status.success()) {
+        (true, _) => Err(Error::Interrupted),
+        (false, true) => Ok(()),
+        (false, false) => Err(Error::Exited(status.code().unwrap_or(1))),
+    }
+}
+
+/// Has the user pressed Ctrl-C?
+pub(crate) fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Forward Ctrl-C to the running application instead of exiting straight away.
+///
+/// The application runs in its own process group, so the terminal only
+/// signals leap. The first Ctrl-C asks the application to shut down, a second
+/// one kills it.
+pub(crate) fn handle_interrupts() {
+    HANDLER.call_once(|| {
+        let result = ctrlc::set_handler(|| {
+            let again = INTERRUPTED.swap(true, Ordering::SeqCst);
+            let pid = CHILD.load(Ordering::SeqCst);
+            if pid != 0 {
+                signal(pid, again);
+            }
+        });
+        if let Err(e) = result {
+            log::error!("unable to handle Ctrl-C because {}", e);
+        }
+    });
+}
+
+#[cfg(unix)]
+fn signal(pid: u32, kill: bool) {
+    let signal = if kill { libc::SIGKILL } else { libc::SIGINT };
+    unsafe {
+        libc::kill(pid as libc::pid_t, signal);
+    }
+}
+
+#[cfg(not(unix))]
+fn signal(_pid: u32, _kill: bool) {
+    // The console delivers Ctrl-C to every attached process
+}
+
+/// A running application process
+pub(crate) struct Server {
+    child: Child,
+}
+
+impl Server {
+    /// Start the application binary with the serve options
+    pub fn start(
+        root: &Path,
+        executable: &Path,
+        options: &ServeOptions,
+    ) -> Result<Server, std::io::Error> {
+        let mut command = Command::new(executable);
+        command
+            .current_dir(root)
+            .arg("--host")
+            .arg(&options.host)
+            .arg("--port")
+            .arg(options.port.to_string())
+            .arg("--env")
+            .arg(&options.environment)
+            .args(&options.args)
+            .env("LEAP_HOST", &options.host)
+            .env("LEAP_PORT", options.port.to_string())
+            .env("LEAP_ENV", &options.environment);
+        if output::json() {
+            // Keep stdout for the JSON result
+            command.stdout(std::io::stderr());
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+
+        log::info!("running: {:?}", &command);
+        let child = command.spawn()?;
+        CHILD.store(child.id(), Ordering::SeqCst);
+        Ok(Server { child })
+    }
+
+    /// The process id of the application
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    /// Wait for the application to exit
+    pub fn wait(&mut self) -> Result<ExitStatus, std::io::Error> {
+        let status = self.child.wait();
+        CHILD.store(0, Ordering::SeqCst);
+        status
+    }
+
+    /// Check whether the application has exited without blocking
+    pub fn try_wait(&mut self) -> Result<Option<ExitStatus>, std::io::Error> {
+        let status = self.child.try_wait()?;
+        if status.is_some() {
+            CHILD.store(0, Ordering::SeqCst);
+        }
+        Ok(status)
+    }
+
+    /// Ask the application to shut down, killing it if it takes too long
+    pub fn stop(&mut self) -> Result<ExitStatus, std::io::Error> {
+        if let Some(status) = self.try_wait()? {
+            return Ok(status);
+        }
+
+        if cfg!(unix) {
+            signal(self.child.id(), false);
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < deadline {
+                if let Some(status) = self.try_wait()? {
+                    return Ok(status);
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        }
+
+        log::info!("killing {}", self.child.id());
+        self.child.kill()?;
+        self.wait()
+    }
+}
@@ -0,0 +1,276 @@
+//! Where project templates come from
+//!
+//! `leap new --template <source>` accepts:
+//!
+//! - a directory, which is used as it is
+//! - a `.zip` or `.tar.gz` file
+//! - an `http://` or `https://` URL of a `.zip` or `.tar.gz` file
+//! - `owner/repo` or `owner/repo@tag` on GitHub, or on the GitHub compatible
+//!   API set as `github.api_url` in the config
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use semver::{Version, VersionReq};
+
+use crate::cli::config::TemplateConfig;
+use crate::cli::error::Error;
+use crate::cli::http::HttpClient;
+use crate::cli::{cache, config, download, fs, github, lock, tar, template, verify, zip};
+
+/// Where a project template comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// A template on disk
+    Directory(PathBuf),
+    /// A `.zip` or `.tar.gz` file on disk
+    Archive(PathBuf),
+    /// A `.zip` or `.tar.gz` file to download
+    Url(String),
+    /// A repository at a release tag, or its latest release
+    Repository { name: String, tag: Option<String> },
+}
+
+impl FromStr for Source {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        if source.starts_with("http://") || source.starts_with("https://") {
+            return Ok(Source::Url(source.to_string()));
+        }
+
+        let path = Path::new(source);
+        if path.is_dir() {
+            return Ok(Source::Directory(path.to_path_buf()));
+        }
+        let lowercase = source.to_lowercase();
+        let archive = [".zip", ".tar.gz", ".tgz"]
+            .iter()
+            .any(|extension| lowercase.ends_with(extension));
+        match (path.is_file(), archive) {
+            (true, true) => return Ok(Source::Archive(path.to_path_buf())),
+            (true, false) => return Err(format!("{} is not a .zip or .tar.gz file", source)),
+            (false, true) => return Err(format!("{} does not exist", source)),
+            (false, false) => {}
+        }
+
+        let (name, tag) = match source.split_once('@') {
+            Some((name, tag)) => (name, Some(tag.to_string())),
+            None => (source, None),
+        };
+        let valid = |part: &str| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+        };
+        let repository = match name.split_once('/') {
+            Some((owner, repo)) => valid(owner) && valid(repo),
+            None => false,
+        };
+        if repository && tag.as_deref() != Some("") {
+            return Ok(Source::Repository {
+                name: name.to_string(),
+                tag,
+            });
+        }
+
+        Err(format!(
+            "{} is not a directory, archive, URL or owner/repo",
+            source
+        ))
+    }
+}
+
+/// Archive formats a template can be packed in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    /// Recognise an archive from its first bytes
+    fn of(contents: &[u8]) -> Option<Format> {
+        if contents.starts_with(b"PK\x03\x04") {
+            Some(Format::Zip)
+        } else if contents.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::TarGz)
+        } else {
+            None
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::TarGz => "tar.gz",
+        }
+    }
+}
+
+/// Get the template from `source`, returning how to record it and where its files are
+pub fn fetch(
+    client: &dyn HttpClient,
+    source: &Source,
+    api_url: &str,
+    requirement: Option<&VersionReq>,
+    pre: bool,
+) -> Result<(TemplateConfig, PathBuf), Error> {
+    let released = matches!(source, Source::Repository { tag: None, .. });
+    if requirement.is_some() && !released {
+        return Err(Error::Usage(format!(
+            "--template-version cannot be used with {}",
+            source_name(source)
+        )));
+    }
+
+    match source {
+        Source::Directory(path) => {
+            let path = path.canonicalize().map_err(|e| Error::io(path, e))?;
+            let template = unversioned(path.display().to_string());
+            Ok((template, path))
+        }
+        Source::Archive(path) => {
+            let contents = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+            let path = path.canonicalize().map_err(|e| Error::io(path, e))?;
+            unpack(path.display().to_string(), &contents)
+        }
+        Source::Url(url) => {
+            log::info!("downloading template {}", url);
+            // Named after the URL, so an interrupted download is only resumed from the same URL
+            let hash = lock::hash(url.as_bytes());
+            let dir = cache::dir()?.join(format!("download-{}", &hash["sha256:".len()..][..12]));
+            let name = url
+                .rsplit('/')
+                .find(|name| !name.is_empty())
+                .unwrap_or("template");
+            let downloaded = dir.join(name.split('?').next().unwrap_or(name));
+            if let Err(e) = download::to_file(client, url, &downloaded) {
+                // Only goes when there is nothing to resume from
+                let _ = std::fs::remove_dir(&dir);
+                return Err(e);
+            }
+            let contents = std::fs::read(&downloaded).map_err(|e| Error::io(&downloaded, e))?;
+            let published = verify::Published::at(client, url).and_then(|published| {
+                let public_key = config::config()?.verify.public_key;
+                verify::published(&downloaded, &contents, &published, &public_key)
+            });
+            let _ = std::fs::remove_dir_all(&dir);
+            published?;
+            unpack(url.clone(), &contents)
+        }
+        Source::Repository { name, tag } => {
+            let versions = github::get_template_versions(client, api_url, name)?;
+            let found = match tag {
+                Some(tag) => versions
+                    .iter()
+                    .find(|version| version.name == tag.trim_start_matches('v'))
+                    .ok_or_else(|| {
+                        Error::Template(format!("{} has no release tagged {}", name, tag))
+                    })?,
+                None => github::latest(&versions, requirement, pre).ok_or_else(|| {
+                    Error::Template(match requirement {
+                        Some(requirement) => {
+                            format!("{} has no release matching {}", name, requirement)
+                        }
+                        None => format!("{} has not been released yet", name),
+                    })
+                })?,
+            };
+            template::fetch(client, api_url, name, found)
+        }
+    }
+}
+
+/// How a source was given on the command line
+fn source_name(source: &Source) -> String {
+    match source {
+        Source::Directory(path) | Source::Archive(path) => path.display().to_string(),
+        Source::Url(url) => url.clone(),
+        Source::Repository {
+            name,
+            tag: Some(tag),
+        } => format!("{}@{}", name, tag),
+        Source::Repository { name, tag: None } => name.clone(),
+    }
+}
+
+/// Unpack an archive into the cache, named after its contents so the same
+/// archive is only unpacked once
+fn unpack(source: String, contents: &[u8]) -> Result<(TemplateConfig, PathBuf), Error> {
+    let format = Format::of(contents)
+        .ok_or_else(|| Error::Template(format!("{} is not a .zip or .tar.gz file", source)))?;
+    let hash = lock::hash(contents);
+    let name = format!("template-{}", &hash["sha256:".len()..][..12]);
+
+    let dir = cache::dir()?;
+    let compressed = format!("{}.{}", name, format.extension());
+    let extracted = dir.join(&name);
+    let recorded = extracted.exists();
+    if !recorded {
+        std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        let path = dir.join(&compressed);
+        std::fs::write(&path, contents).map_err(|e| Error::io(&path, e))?;
+        match format {
+            Format::Zip => zip::extract(&path, &extracted)?,
+            Format::TarGz => tar::extract(&path, &extracted)?,
+        }
+    }
+
+    let template = TemplateConfig {
+        hash,
+        compressed,
+        extracted: name,
+        ..unversioned(source)
+    };
+    if !recorded {
+        cache::record(&dir, &template)?;
+    }
+    let root = fs::root(&extracted).map_err(|e| Error::io(&extracted, e))?;
+    Ok((template, root))
+}
+
+/// How to record a template which is not a release
+fn unversioned(source: String) -> TemplateConfig {
+    TemplateConfig {
+        hash: String::new(),
+        version: Version::parse("0.0.0").unwrap(),
+        compressed: String::new(),
+        extracted: String::new(),
+        source,
+        tag: String::new(),
+        sha: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_works() {
+        assert_eq!(
+            "https://example.com/template.zip".parse(),
+            Ok(Source::Url("https://example.com/template.zip".to_string()))
+        );
+        assert_eq!(
+            "acme/leap-template".parse(),
+            Ok(Source::Repository {
+                name: "acme/leap-template".to_string(),
+                tag: None
+            })
+        );
+        assert_eq!(
+            "acme/leap-template@v1.2.0".parse(),
+            Ok(Source::Repository {
+                name: "acme/leap-template".to_string(),
+                tag: Some("v1.2.0".to_string())
+            })
+        );
+        assert_eq!("src".parse(), Ok(Source::Directory(PathBuf::from("src"))));
+        assert!("Cargo.toml".parse::<Source>().is_err());
+        assert!("acme/leap-template@".parse::<Source>().is_err());
+        assert!("nowhere".parse::<Source>().is_err());
+    }
+}
@@ -0,0 +1,127 @@
+//! Extracting .tar.gz archives, see [`extract`](crate::cli::extract) for what
+//! is checked
+
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use tar::EntryType;
+
+use crate::cli::error::Error;
+use crate::cli::extract::{self, ExtractError, Limits, Unpacker};
+
+/// Extract a `.tar.gz` archive, within the configured limits
+pub fn extract(path: &Path, extract_to: &Path) -> Result<(), Error> {
+    extract_with(path, extract_to, Limits::configured()?)
+}
+
+/// Extract a `.tar.gz` archive to `extract_to`, which must not exist yet
+pub fn extract_with(path: &Path, extract_to: &Path, limits: Limits) -> Result<(), Error> {
+    extract::staged(path, extract_to, |file, staging| {
+        unpack(file, staging, limits)
+    })
+}
+
+/// Extract every entry of an archive into `to`
+fn unpack(file: std::fs::File, to: &Path, limits: Limits) -> Result<(), ExtractError> {
+    let unreadable = |e: std::io::Error| ExtractError::Unreadable(e.to_string());
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+
+    let mut unpacker = Unpacker::new(to, limits)?;
+    for entry in archive.entries().map_err(unreadable)? {
+        let mut entry = entry.map_err(unreadable)?;
+        let entry_type = entry.header().entry_type();
+        // Metadata for the entries, such as the commit GitHub records
+        if let EntryType::XGlobalHeader
+        | EntryType::XHeader
+        | EntryType::GNULongName
+        | EntryType::GNULongLink = entry_type
+        {
+            continue;
+        }
+
+        unpacker.count()?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        let outpath = match unpacker.path(&name)? {
+            Some(outpath) => outpath,
+            None => continue,
+        };
+        let mode = entry.header().mode().ok();
+        match entry_type {
+            EntryType::Directory => unpacker.dir(&outpath, mode)?,
+            EntryType::Regular | EntryType::Continuous => {
+                let size = entry.size();
+                unpacker.file(&outpath, size, &mut entry, mode)?
+            }
+            EntryType::Symlink | EntryType::Link => return Err(ExtractError::Link(name)),
+            _ => return Err(ExtractError::Special(name)),
+        }
+    }
+
+    Ok(())
+}
+
+/// A .tar.gz of `entries` for tests, with headers written as they are as the
+/// builder would refuse unsafe names
+#[cfg(test)]
+pub fn archive(entries: &[extract::Entry<'_>]) -> Vec<u8> {
+    use extract::Entry;
+    use flate2::write::GzEncoder;
+
+    let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Default::default()));
+    for entry in entries {
+        let mut header = tar::Header::new_old();
+        let (name, contents) = match *entry {
+            Entry::Dir(name) => {
+                header.set_entry_type(EntryType::Directory);
+                header.set_mode(0o755);
+                (name, &b""[..])
+            }
+            Entry::File(name, contents, mode) => {
+                header.set_entry_type(EntryType::Regular);
+                header.set_mode(mode);
+                (name, contents)
+            }
+            Entry::Symlink(name, target) => {
+                header.set_entry_type(EntryType::Symlink);
+                header.set_mode(0o777);
+                header.as_old_mut().linkname[..target.len()].copy_from_slice(target.as_bytes());
+                (name, &b""[..])
+            }
+        };
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_size(contents.len() as u64);
+        header.set_cksum();
+        tar.append(&header, contents).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::{left, scratch};
+    use flate2::write::GzEncoder;
+
+    #[test]
+    fn extract_refuses_special_files() {
+        let dir = scratch("tar-special");
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut tar = tar::Builder::new(GzEncoder::new(Vec::new(), Default::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_path("fifo").unwrap();
+        header.set_entry_type(EntryType::Fifo);
+        header.set_size(0);
+        header.set_cksum();
+        tar.append(&header, &b""[..]).unwrap();
+        let path = dir.join("template.tar.gz");
+        std::fs::write(&path, tar.into_inner().unwrap().finish().unwrap()).unwrap();
+
+        let limits = Limits {
+            entries: 100,
+            size: 1024,
+        };
+        let extracted = extract_with(&path, &dir.join("template"), limits);
+        assert!(extracted.unwrap_err().to_string().contains("not a file"));
+        assert!(left(&dir, "template.tar.gz").is_empty());
+    }
+}
@@ -1,164 +1,639 @@
 //! Template manager
 
-use semver::Version;
+use std::path::{Path, PathBuf};
 
-use crate::cli::{config, download, github};
-use crate::cli::config::{TemplateConfig, UpdateConfig};
+use semver::{Version, VersionReq};
+
+use crate::cli::config::{Config, Refresh, TemplateConfig, UpdateConfig};
+use crate::cli::error::Error;
+use crate::cli::github::Tag;
+use crate::cli::http::HttpClient;
+use crate::cli::lock::{self, Lock};
+use crate::cli::manifest::{self, HookPolicy, Manifest};
+use crate::cli::output::{self, Generated};
+use crate::cli::render::{self, RenderError, Value, Variables};
+use crate::cli::source::{self, Source};
 use crate::cli::zip;
+use crate::cli::{cache, config, download, extract, fs, github, verify};
 
-pub fn update() {
-    let config = config::config();
-    if config.is_err() {
-        log::error!(
-            "Unable to get config - {}",
-            config.err().unwrap().to_string()
-        );
-        return;
+/// Download the latest release of the project template when a check is due,
+/// see `update.refresh` in the config, or straight away with `force`
+pub fn update(client: &dyn HttpClient, force: bool) -> Result<(), Error> {
+    let config = config::config()?;
+    let settings = config::settings()?;
+    let refresh = if force {
+        Refresh::Always
+    } else {
+        settings.update.refresh
+    };
+
+    let checked = check(
+        client,
+        &config,
+        &settings.github.api_url,
+        refresh,
+        &cache::dir()?,
+        now(),
+    )?;
+    match checked {
+        Some(config) => config::write(config),
+        None => Ok(()),
+    }
+}
+
+/// Download the latest release of the project template into `dir` when it
+/// has not been downloaded or `refresh` says a check is due, returning the
+/// config which remembers it
+fn check(
+    client: &dyn HttpClient,
+    config: &Config,
+    api_url: &str,
+    refresh: Refresh,
+    dir: &Path,
+    now: u64,
+) -> Result<Option<Config>, Error> {
+    let compressed = dir.join(&config.template.compressed);
+    if !compressed.exists() || refresh.due(config.update.last as u64, now) {
+        log::info!("checking for a newer release of the project template");
+        let versions =
+            github::get_template_versions(client, api_url, github::PROJECT_TEMPLATE_REPOSITORY)?;
+        let latest_tags = github::latest(&versions, None, false).ok_or_else(|| {
+            Error::Template("the project template has not been released yet".to_string())
+        })?;
+        let (template, _) = fetch_to(
+            client,
+            api_url,
+            &config.verify.public_key,
+            dir,
+            github::PROJECT_TEMPLATE_REPOSITORY,
+            latest_tags,
+        )?;
+
+        let config = config.with_template(template).with_update(UpdateConfig {
+            last: now as usize,
+            ..config.update.clone()
+        });
+        return Ok(Some(config));
     }
 
-    let config = config.unwrap();
-    let dir = config::dir().unwrap();
-    let now: usize = std::time::SystemTime::now()
+    let hash = cache::verify(dir, &config.template)?;
+    let extracted = dir.join(&config.template.extracted);
+    if !extracted.exists() {
+        zip::extract(&compressed, &extracted)?;
+    }
+    if hash != config.template.hash {
+        // Recorded for the first time
+        return Ok(Some(config.with_template(TemplateConfig {
+            hash,
+            ..config.template.clone()
+        })));
+    }
+    Ok(None)
+}
+
+/// The names used to cache a released version of a template repository, its
+/// checksum is only known once it has been downloaded
+pub fn template_config(repository: &str, tag: &Tag) -> Result<TemplateConfig, Error> {
+    let short_sha = &tag.sha[..7.min(tag.sha.len())];
+    let version = Version::parse(&tag.name)
+        .map_err(|e| Error::Template(format!("{} is not a version because {}", tag.name, e)))?;
+    Ok(TemplateConfig {
+        hash: String::new(),
+        version,
+        compressed: format!(r"template-{}.zip", short_sha),
+        extracted: format!(r"{}-{}", repository.replace('/', "-"), short_sha),
+        source: repository.to_string(),
+        tag: tag.tag.clone(),
+        sha: tag.sha.clone(),
+    })
+}
+
+/// Download and extract a released version of a template repository, unless
+/// it is already cached
+pub fn fetch(
+    client: &dyn HttpClient,
+    api_url: &str,
+    repository: &str,
+    tag: &Tag,
+) -> Result<(TemplateConfig, PathBuf), Error> {
+    let public_key = config::config()?.verify.public_key;
+    fetch_to(
+        client,
+        api_url,
+        &public_key,
+        &cache::dir()?,
+        repository,
+        tag,
+    )
+}
+
+/// Download and extract a released version of a template repository into
+/// `dir`, unless it is already there. Downloads are checked against what the
+/// release publishes, and cached archives against their recorded checksum.
+fn fetch_to(
+    client: &dyn HttpClient,
+    api_url: &str,
+    public_key: &str,
+    dir: &Path,
+    repository: &str,
+    tag: &Tag,
+) -> Result<(TemplateConfig, PathBuf), Error> {
+    let mut template = template_config(repository, tag)?;
+    let compressed = dir.join(&template.compressed);
+    let extracted = dir.join(&template.extracted);
+
+    let downloaded = !compressed.exists();
+    if downloaded {
+        log::info!("downloading template {}", &tag.name);
+        download::to_file(client, &tag.zipball, &compressed)?;
+        let data = std::fs::read(&compressed).map_err(|e| Error::io(&compressed, e))?;
+        let checked = if data.is_empty() {
+            Err(Error::Template("the project template is empty".to_string()))
+        } else {
+            verify::Published::released(client, api_url, repository, &template.tag)
+                .and_then(|published| verify::published(&compressed, &data, &published, public_key))
+        };
+        if let Err(e) = checked {
+            let _ = std::fs::remove_file(&compressed);
+            return Err(e);
+        }
+        template.hash = lock::hash(&data);
+    } else {
+        template.hash = cache::verify(dir, &template)?;
+    }
+
+    let extract = !extracted.exists();
+    if extract {
+        zip::extract(&compressed, &extracted)?;
+    }
+    if downloaded || extract {
+        cache::record(dir, &template)?;
+    }
+
+    let path = fs::root(&extracted).map_err(|e| Error::io(&extracted, e))?;
+    Ok((template, path))
+}
+
+/// The variables available to every template
+pub fn variables(name: &str) -> Variables {
+    let mut variables = Variables::new();
+    variables.insert("project_name".to_string(), name.into());
+    for case in ["snake", "kebab", "pascal"].iter() {
+        if let Ok(value) = render::filter(case, name) {
+            variables.insert(format!("project_{}", case), value.into());
+        }
+    }
+    variables.insert("author".to_string(), author().into());
+    variables.insert("year".to_string(), year(now()).to_string().into());
+    variables
+}
+
+/// The name of the person generating the project
+fn author() -> String {
+    let git = std::process::Command::new("git")
+        .args(["config", "user.name"])
+        .output();
+    if let Ok(output) = git {
+        let name = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        if output.status.success() && !name.is_empty() {
+            return name;
+        }
+    }
+
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+/// Seconds since the unix epoch
+pub fn now() -> u64 {
+    std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .expect("The day the earth stood still?")
-        .as_secs() as usize;
-
-    let last_six_months = now - 15_778_800_usize;
-    let its_been_ages = last_six_months < config.update.last;
-
-    // todo: check &config.update.last
-    if its_been_ages || !dir.data_dir().join(&config.template.compressed).exists() {
-        match github::get_leap_project_template_versions() {
-            Ok(versions) => {
-                let latest_tags = versions.first().unwrap();
-                let url = &latest_tags.zipball;
-                // Update Config
-                let (short_sha, _) = &latest_tags.sha.split_at(7);
-                let version = Version::parse(&latest_tags.name).unwrap();
-
-                let config = config.with_template(TemplateConfig {
-                    hash: format!(r"template-{}.zip", short_sha),
-                    version,
-                    compressed: format!(r"template-{}.zip", short_sha),
-                    extracted: format!(r"daniel-samson-leap-project-template-{}", short_sha),
-                });
-
-                let config = config.with_update(UpdateConfig { last: now });
-
-                // Persist Config
-                match config::write(config.clone()) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        log::error!("unable to update config because {}", e.to_string());
-                        return;
-                    }
-                };
-                // Download
-                match download::get(url) {
-                    Ok(data) => {
-                        if data.is_empty() {
-                            log::error!("the project template is empty");
-                            return;
-                        };
-
-                        match std::fs::create_dir_all(&dir.data_dir()) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                log::error!(
-                                    "unable to create cache project template because {}",
-                                    e.to_string()
-                                );
-                                return;
-                            }
-                        };
-
-                        match std::fs::write(
-                            &dir.data_dir().join(&config.template.compressed),
-                            data,
-                        ) {
-                            Ok(_) => {}
-                            Err(e) => {
-                                log::error!(
-                                    "unable to cache project template because {}",
-                                    e.to_string()
-                                );
-                                return;
-                            }
-                        };
-                    }
-                    Err(e) => {
-                        log::error!(
-                            "unable to get the latest version of the project template because {}",
-                            e.as_ref()
-                        );
-                        return;
-                    }
-                };
-            }
-            Err(e) => {
-                log::error!(
-                    "unable to get the latest version of the project template because {}",
-                    e.as_ref()
-                );
-                return;
-            }
-        };
+        .as_secs()
+}
+
+/// The year of a unix timestamp
+fn year(timestamp: u64) -> i64 {
+    civil(timestamp).0
+}
+
+/// The date of a unix timestamp, as `YYYY-MM-DD`
+pub fn date(timestamp: u64) -> String {
+    let (year, month, day) = civil(timestamp);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// The year, month and day of a unix timestamp
+fn civil(timestamp: u64) -> (i64, i64, i64) {
+    // Days to civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let year = year_of_era + era * 400;
+    if month >= 10 {
+        (year + 1, month - 9, day)
+    } else {
+        (year, month + 3, day)
     }
+}
 
-    if !dir.data_dir().join(&config.template.extracted).exists() {
-        match zip::extract(
-            &dir.data_dir().join(&config.template.compressed),
-            &dir.data_dir().join(&config.template.extracted),
-        ) {
-            Ok(_) => {}
-            Err(e) => {
-                log::error!(
-                    "unable to extract project template because {}",
-                    e
-                );
-                return;
-            }
+/// Render a template path into a project path.
+///
+/// Returns `None` when a part of the path renders to nothing, which is how a
+/// template leaves a file out.
+pub fn render_path(relative: &Path, variables: &Variables) -> Result<Option<PathBuf>, Error> {
+    let mut path = PathBuf::new();
+    for component in relative.components() {
+        let rendered = render::render(&component.as_os_str().to_string_lossy(), variables)?;
+        if rendered.is_empty() {
+            return Ok(None);
+        }
+        if rendered == "." || rendered == ".." || rendered.contains(['/', '\\']) {
+            return Err(Error::Template(format!(
+                "{:?} renders to an invalid path",
+                relative
+            )));
         }
+        path.push(rendered);
     }
+    Ok(Some(path))
 }
 
-pub fn new_project(name: &str) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let mut config = config::config()?;
-    let dir = config::dir().unwrap();
-    let template_path = dir.data_dir()
-        .join(&config.template.extracted)
-        .join(&config.template.extracted);
+/// Customise a template file for a project. Binary files are left untouched.
+pub fn render(
+    variables: &Variables,
+    relative: &Path,
+    contents: Vec<u8>,
+) -> Result<Vec<u8>, RenderError> {
+    if render::is_binary(&contents) {
+        return Ok(contents);
+    }
+
+    let text = String::from_utf8_lossy(&contents);
+    let mut text = render::render(&text, variables)?;
+    if relative == Path::new("Cargo.toml") {
+        if let Some(Value::Text(name)) = variables.get("project_name") {
+            text = text.replace("leap-project-template", name);
+        }
+    }
+    Ok(text.into_bytes())
+}
+
+/// Read a file of a template, and its permissions. Symlinks are refused
+/// rather than followed to somewhere outside of the template.
+pub fn read_file(
+    template: &Path,
+    relative: &Path,
+) -> Result<(Vec<u8>, std::fs::Permissions), Error> {
+    let source = template.join(relative);
+    let metadata = std::fs::symlink_metadata(&source).map_err(|e| Error::io(&source, e))?;
+    if !metadata.is_file() {
+        return Err(Error::Template(format!(
+            "{} is not a file, templates may only contain files and directories",
+            relative.display()
+        )));
+    }
+    let contents = std::fs::read(&source).map_err(|e| Error::io(&source, e))?;
+    Ok((contents, metadata.permissions()))
+}
+
+/// Render every file of a template into `project`, recording them in the lock
+pub fn generate(
+    template: &Path,
+    project: &Path,
+    manifest: &Manifest,
+    mut lock: Lock,
+) -> Result<Lock, Error> {
+    for relative in fs::files(template).map_err(|e| Error::io(template, e))? {
+        if !manifest.includes(&relative, &lock.variables) {
+            continue;
+        }
+        let source = template.join(&relative);
+        let target = match render_path(&relative, &lock.variables)? {
+            Some(target) => target,
+            None => continue,
+        };
+        let (contents, permissions) = read_file(template, &relative)?;
+        let contents = render(&lock.variables, &target, contents).map_err(|e| {
+            Error::Template(format!(
+                "unable to render {} because {}",
+                relative.display(),
+                e
+            ))
+        })?;
+
+        let path = project.join(&target);
+        log::info!("  copy: {:?} -> {:?}", &source, &path);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        std::fs::write(&path, &contents).map_err(|e| Error::io(&path, e))?;
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
 
-    if !template_path.exists() {
-        update();
-        // reload config to get latest path names
-        config = config::config()?;
+            std::fs::Permissions::from_mode(extract::permissions(permissions.mode(), false))
+        };
+        std::fs::set_permissions(&path, permissions).map_err(|e| Error::io(&path, e))?;
+        lock = lock.with_file(&target, &contents);
     }
 
-    let template_path = dir.data_dir()
-        .join(&config.template.extracted)
-        .join(&config.template.extracted);
+    Ok(lock)
+}
 
-    log::info!("renaming template...");
-    let action = crate::cli::fs::copy(
-        &template_path,
-        std::env::current_dir()?.join(name),
-    );
+/// Options for the new sub-command
+#[derive(Debug, Clone, Default)]
+pub struct NewOptions {
+    /// The name of the project and its directory
+    pub name: String,
+    /// Values for template variables given with `--define key=value`
+    pub defines: Vec<(String, String)>,
+    /// Generate the project from this template instead of the leap project template
+    pub template: Option<Source>,
+    /// Use the newest release matching this requirement rather than the latest
+    pub version: Option<VersionReq>,
+    /// Consider prereleases
+    pub pre: bool,
+    /// Only use templates which have already been downloaded
+    pub offline: bool,
+    /// Check for a newer release of the project template, however recently it was checked
+    pub refresh: bool,
+    /// Whether to run the template's hooks
+    pub hooks: HookPolicy,
+}
 
-    log::info!("copying template...");
-    match action {
-        Ok(_) => {
-            let cargo = std::env::current_dir()?.join(name).join("Cargo.toml");
-            let updated_cargo = std::fs::read_to_string(&cargo)
-                .expect("Unable to read Cargo.toml")
-                .replace("leap-project-template", name);
-            std::fs::write(cargo, updated_cargo)
-                .expect("Unable to write Cargo.toml");
+impl NewOptions {
+    /// Is the project generated from the cached latest release of the leap project template?
+    pub fn latest_template(&self) -> bool {
+        self.template.is_none() && self.version.is_none() && !self.pre
+    }
+}
+
+pub fn new_project(client: &dyn HttpClient, options: &NewOptions) -> Result<Generated, Error> {
+    let name = options.name.as_str();
+    let mut config = config::config()?;
+    let dir = cache::dir()?;
+
+    let (template, template_path) = if options.offline {
+        cached(client, options)?
+    } else if options.latest_template() {
+        if !dir.join(&config.template.extracted).exists() {
+            if let Err(e) = update(client, options.refresh) {
+                log::warn!("unable to update the project template because {}", e);
+            }
+            // reload config to get latest path names
+            config = config::config()?;
         }
+
+        let template_path = dir.join(&config.template.extracted);
+        if template_path.exists() {
+            let root = fs::root(&template_path).map_err(|e| Error::io(&template_path, e))?;
+            (config.template, root)
+        } else {
+            say!("Unable to download the project template, looking for a cached copy");
+            cached(client, options)?
+        }
+    } else {
+        let default = Source::Repository {
+            name: github::PROJECT_TEMPLATE_REPOSITORY.to_string(),
+            tag: None,
+        };
+        let fetched = source::fetch(
+            client,
+            options.template.as_ref().unwrap_or(&default),
+            &config::settings()?.github.api_url,
+            options.version.as_ref(),
+            options.pre,
+        );
+        match fetched {
+            Err(e) if e.is_network() => {
+                say!("{}, looking for a cached copy", e);
+                cached(client, options)?
+            }
+            fetched => fetched?,
+        }
+    };
+
+    log::info!("copying template...");
+    let project = std::env::current_dir()?.join(name);
+    if project.exists() {
+        return Err(Error::Project(format!(
+            "{} already exists",
+            project.display()
+        )));
+    }
+
+    let manifest = manifest::read(&template_path)?;
+    let mut variables = variables(name);
+    manifest.resolve(&mut variables, &options.defines, manifest::interactive())?;
+
+    let lock = Lock::new(name, &template).with_variables(variables);
+    let generated = generate(&template_path, &project, &manifest, lock).and_then(|lock| {
+        lock::write(&project, &lock)?;
+        Ok(lock)
+    });
+    let lock = match generated {
+        Ok(lock) => lock,
         Err(e) => {
-            println!("Unable to create project because {}", e);
+            // Don't leave a half generated project behind
+            let _ = std::fs::remove_dir_all(&project);
+            return Err(e);
+        }
+    };
+
+    // Only the leap project template is trusted to run commands unasked
+    let trusted = options.template.is_none();
+    manifest.run_hooks(&project, &lock.variables, options.hooks, trusted)?;
+    Ok(Generated {
+        name: name.to_string(),
+        path: project,
+        template: output::Template::new(&template, Some(template_path)),
+    })
+}
+
+/// The newest cached copy of the template asked for, for working offline
+fn cached(
+    client: &dyn HttpClient,
+    options: &NewOptions,
+) -> Result<(TemplateConfig, PathBuf), Error> {
+    let (source, requirement) = match &options.template {
+        None => (
+            github::PROJECT_TEMPLATE_REPOSITORY.to_string(),
+            options.version.clone(),
+        ),
+        Some(Source::Repository {
+            name,
+            tag: Some(tag),
+        }) => (name.clone(), Some(github::version_requirement(tag)?)),
+        Some(Source::Repository { name, tag: None }) => (name.clone(), options.version.clone()),
+        Some(Source::Url(url)) => (url.clone(), None),
+        // Templates on disk don't need the network
+        Some(local) => {
+            return source::fetch(client, local, "", options.version.as_ref(), options.pre)
+        }
+    };
+
+    match cache::newest(&source, requirement.as_ref(), options.pre)? {
+        Some((template, path)) => {
+            say!(
+                "Using cached template {} {}",
+                template.source,
+                template.version
+            );
+            Ok((template, path))
         }
+        None => {
+            let wanted = match &requirement {
+                Some(requirement) => format!("{} {}", source, requirement),
+                None => source,
+            };
+            Err(Error::Template(format!(
+                "{} has not been downloaded yet, connect to the network and try again",
+                wanted
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::http::{Mock, Response};
+    use std::io::Write;
+
+    /// A GitHub zipball of a template with a single file
+    fn zipball() -> Vec<u8> {
+        let mut zip = ::zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let options = ::zip::write::FileOptions::default();
+        zip.add_directory("owner-template-abc1234/", options)
+            .unwrap();
+        zip.start_file("owner-template-abc1234/README.md", options)
+            .unwrap();
+        zip.write_all(b"# {{ project_name }}\n").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn check_works() {
+        let dir = extract::scratch("check");
+        let api_url = "http://github.test";
+        let repository = format!("{}/repos/{}", api_url, github::PROJECT_TEMPLATE_REPOSITORY);
+        let tags_url = format!("{}/tags", repository);
+        let zipball_url = format!("{}/zipball/v0.3.0", api_url);
+        let zipball = zipball();
+        let checksum_url = format!("{}/download/source.zip.sha256", api_url);
+        let checksum = lock::hash(&zipball).replace("sha256:", "");
+        let client = Mock::new()
+            .json(&repository, serde_json::json!({ "tags_url": tags_url }))
+            .json(
+                &tags_url,
+                serde_json::json!([{
+                    "name": "v0.3.0",
+                    "zipball_url": zipball_url,
+                    "tarball_url": "",
+                    "commit": { "sha": "abc1234def" },
+                }]),
+            )
+            .route(&zipball_url, Response::ok(zipball))
+            .json(
+                &format!("{}/releases/tags/v0.3.0", repository),
+                serde_json::json!({
+                    "tag_name": "v0.3.0",
+                    "assets": [{
+                        "name": "source.zip.sha256",
+                        "browser_download_url": checksum_url,
+                    }],
+                }),
+            )
+            .route(&checksum_url, Response::ok(checksum));
+        let hour = Refresh::Every(3600);
+
+        // Nothing has been downloaded yet
+        let config = check(&client, &Config::default(), api_url, hour, &dir, 1000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.template.version, Version::parse("0.3.0").unwrap());
+        assert_eq!(config.template.sha, "abc1234def");
+        assert_eq!(config.update.last, 1000);
+        assert!(dir.join(&config.template.compressed).exists());
+        let root = fs::root(dir.join(&config.template.extracted)).unwrap();
+        assert!(root.join("README.md").exists());
+        assert!(dir.join(cache::FILE_NAME).exists());
+        assert!(verify::is_checksum(&config.template.hash));
+        assert_eq!(client.requested().len(), 5);
+
+        // Checked recently
+        assert!(check(&client, &config, api_url, hour, &dir, 2000)
+            .unwrap()
+            .is_none());
+        assert_eq!(client.requested().len(), 5);
+
+        // Forced, the release is already cached so only the API is asked
+        let forced = check(&client, &config, api_url, Refresh::Always, &dir, 2000)
+            .unwrap()
+            .unwrap();
+        assert_eq!(forced.update.last, 2000);
+        assert_eq!(client.requested().len(), 7);
+
+        // Offline
+        let offline = Mock::offline();
+        assert!(
+            check(&offline, &config, api_url, Refresh::Never, &dir, 2000)
+                .unwrap()
+                .is_none()
+        );
+        let failed = check(&offline, &config, api_url, Refresh::Always, &dir, 2000);
+        assert!(failed.unwrap_err().is_network());
+
+        // Changed since it was downloaded
+        let compressed = dir.join(&config.template.compressed);
+        let mut archive = std::fs::read(&compressed).unwrap();
+        archive.extend_from_slice(b"tampered");
+        std::fs::write(&compressed, archive).unwrap();
+        let tampered = check(&offline, &config, api_url, Refresh::Never, &dir, 2000);
+        assert!(matches!(tampered, Err(Error::Archive { .. })));
     }
 
-    Ok(())
+    #[cfg(unix)]
+    #[test]
+    fn generate_works() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = extract::scratch("generate");
+        let template = dir.join("template");
+        std::fs::create_dir_all(&template).unwrap();
+        let run = template.join("run.sh");
+        std::fs::write(&run, "echo {{ project_name }}").unwrap();
+        std::fs::set_permissions(&run, std::fs::Permissions::from_mode(0o4777)).unwrap();
+        let lock = Lock::new("demo", &TemplateConfig::default()).with_variables(variables("demo"));
+
+        let project = dir.join("demo");
+        let generated = generate(&template, &project, &Manifest::default(), lock.clone());
+        assert!(generated.is_ok());
+        assert_eq!(std::fs::read(project.join("run.sh")).unwrap(), b"echo demo");
+        let metadata = std::fs::metadata(project.join("run.sh")).unwrap();
+        assert_eq!(metadata.permissions().mode() & 0o7777, 0o755);
+
+        // A template may not reach outside of itself
+        std::os::unix::fs::symlink("/etc", template.join("etc")).unwrap();
+        let project = dir.join("linked");
+        let generated = generate(&template, &project, &Manifest::default(), lock);
+        assert!(matches!(generated, Err(Error::Template(_))));
+        assert!(!project.join("etc").exists());
+    }
+
+    #[test]
+    fn year_works() {
+        assert_eq!(year(0), 1970);
+        assert_eq!(year(1_609_459_199), 2020);
+        assert_eq!(year(1_609_459_200), 2021);
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(1_709_164_800), "2024-02-29");
+    }
 }
@@ -0,0 +1,435 @@
+//! Update leap itself to its latest release
+//!
+//! Releases carry an archive or binary for each platform, named after the
+//! operating system and architecture, e.g. `leap-x86_64-linux.tar.gz`, along
+//! with its SHA-256 checksum in `<asset>.sha256` or a `SHA256SUMS` file. The
+//! download is verified before it replaces the running binary. When
+//! `verify.public_key` is set in the global config, the asset also has to be
+//! signed with that key in `<asset>.minisig`.
+//!
+//! When leap was installed with `cargo install`, cargo is left to update it.
+
+use std::env::consts::{ARCH, OS};
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+
+use crate::cli::error::Error;
+use crate::cli::github::{self, Asset, Release};
+use crate::cli::http::HttpClient;
+use crate::cli::verify::{self, Published};
+use crate::cli::{config, download, fs, lock, tar, zip};
+
+/// How many lines of each release's notes are shown
+const NOTES: usize = 5;
+
+/// Options for `leap update`
+#[derive(Debug, Clone, Default)]
+pub struct UpdateOptions {
+    /// Only report whether a newer version is available
+    pub check: bool,
+}
+
+/// What became of the running leap
+#[derive(Debug, Clone, PartialEq)]
+pub enum Installed {
+    /// It is the latest release
+    UpToDate,
+    /// A newer release is available, when only checking
+    Available,
+    /// The binary at this path was replaced
+    Replaced(PathBuf),
+    /// leap was installed with cargo, which updates it with this command
+    Cargo(String),
+}
+
+/// The outcome of updating leap
+#[derive(Debug)]
+pub struct SelfUpdate {
+    pub current: Version,
+    pub latest: Version,
+    /// Releases newer than the running version, newest first
+    pub newer: Vec<Release>,
+    pub installed: Installed,
+}
+
+/// The version of leap which is running
+pub fn current() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("the package version is a version")
+}
+
+/// Replace the running leap with its latest release, unless it is up to date
+pub fn run(client: &dyn HttpClient, options: &UpdateOptions) -> Result<SelfUpdate, Error> {
+    let current = current();
+    let api_url = config::settings()?.github.api_url;
+    let mut newer: Vec<Release> = github::get_releases(client, &api_url, github::LEAP_REPOSITORY)?
+        .into_iter()
+        .filter(|release| !release.prerelease && !release.version.is_prerelease())
+        .filter(|release| release.version > current)
+        .collect();
+    newer.sort_by(|a, b| b.version.cmp(&a.version));
+
+    let exe = std::env::current_exe()?;
+    let installed = match newer.first() {
+        None => Installed::UpToDate,
+        Some(_) if options.check => Installed::Available,
+        Some(latest) => match cargo_home().filter(|home| installed_by_cargo(&exe, home)) {
+            Some(_) => Installed::Cargo(format!(
+                "cargo install {} --version {}",
+                env!("CARGO_PKG_NAME"),
+                latest.version
+            )),
+            None => {
+                replace(client, &exe, latest)?;
+                Installed::Replaced(exe)
+            }
+        },
+    };
+
+    Ok(SelfUpdate {
+        latest: newer
+            .first()
+            .map_or_else(|| current.clone(), |latest| latest.version.clone()),
+        current,
+        newer,
+        installed,
+    })
+}
+
+/// The first few lines of release notes, without markdown headings
+pub fn summary(notes: &str) -> Vec<String> {
+    let lines: Vec<&str> = notes
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    let mut summary: Vec<String> = lines
+        .iter()
+        .take(NOTES)
+        .map(|line| line.to_string())
+        .collect();
+    if lines.len() > NOTES {
+        summary.push("...".to_string());
+    }
+    summary
+}
+
+/// Where cargo installs binaries from, `$CARGO_HOME` or `~/.cargo`
+fn cargo_home() -> Option<PathBuf> {
+    std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| directories::BaseDirs::new().map(|dirs| dirs.home_dir().join(".cargo")))
+}
+
+/// Is `exe` in cargo's bin directory?
+fn installed_by_cargo(exe: &Path, cargo_home: &Path) -> bool {
+    let bin = cargo_home.join("bin");
+    let bin = bin.canonicalize().unwrap_or(bin);
+    exe.parent()
+        .map(|dir| dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf()))
+        .is_some_and(|dir| dir == bin)
+}
+
+/// The asset built for this operating system and architecture. The
+/// architecture has to be a whole word of the name, so `x86` is not taken
+/// for `x86_64` nor `arm` for `armv7`.
+fn asset<'a>(assets: &'a [Asset], os: &str, arch: &str) -> Option<&'a Asset> {
+    let names: &[&str] = match os {
+        "macos" => &["macos", "darwin", "apple"],
+        "windows" => &["windows", "win64", "msvc"],
+        os => &[os],
+    };
+    assets.iter().find(|asset| {
+        let name = asset.name.to_lowercase();
+        words(&name).contains(&arch)
+            && names.iter().any(|os| name.contains(os))
+            && !name.ends_with(".sha256")
+            && !name.ends_with(".sig")
+            && !name.ends_with(".minisig")
+    })
+}
+
+/// The words of an asset's name, separated by `-`, `.` or `_`. An `_` before
+/// a digit is part of the word, as in `x86_64`.
+fn words(name: &str) -> Vec<&str> {
+    let bytes = name.as_bytes();
+    let mut words = Vec::new();
+    let mut start = 0;
+    for (i, c) in name.char_indices() {
+        let joined = c == '_' && bytes.get(i + 1).is_some_and(u8::is_ascii_digit);
+        if matches!(c, '-' | '.' | '_') && !joined {
+            words.push(&name[start..i]);
+            start = i + 1;
+        }
+    }
+    words.push(&name[start..]);
+    words
+}
+
+/// The published SHA-256 checksum of an asset
+fn checksum(client: &dyn HttpClient, release: &Release, asset: &Asset) -> Result<String, Error> {
+    let own = format!("{}.sha256", asset.name);
+    if let Some(file) = release.assets.iter().find(|file| file.name == own) {
+        let text = String::from_utf8_lossy(&download::get(client, &file.url)?).into_owned();
+        if let Some(sum) = text.split_whitespace().next() {
+            return Ok(sum.to_lowercase());
+        }
+    }
+
+    let list = release.assets.iter().find(|file| {
+        let name = file.name.to_lowercase();
+        name == "sha256sums" || name == "sha256sums.txt" || name == "checksums.txt"
+    });
+    if let Some(file) = list {
+        let text = String::from_utf8_lossy(&download::get(client, &file.url)?).into_owned();
+        if let Some(sum) = listed(&text, &asset.name) {
+            return Ok(sum);
+        }
+    }
+
+    Err(Error::Update(format!(
+        "release {} does not publish a checksum for {}",
+        release.tag, asset.name
+    )))
+}
+
+/// The checksum of `name` in a `sha256sum` style list
+fn listed(list: &str, name: &str) -> Option<String> {
+    list.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        let sum = words.next()?;
+        let file = words.next()?.trim_start_matches('*');
+        (file == name).then(|| sum.to_lowercase())
+    })
+}
+
+/// The minisign signature of an asset, when releases have to be signed with
+/// `public_key`
+fn signature(
+    client: &dyn HttpClient,
+    release: &Release,
+    asset: &Asset,
+    public_key: &str,
+) -> Result<Option<String>, Error> {
+    if public_key.trim().is_empty() {
+        return Ok(None);
+    }
+    let name = format!("{}.minisig", asset.name);
+    match release.assets.iter().find(|file| file.name == name) {
+        Some(file) => Ok(Some(
+            String::from_utf8_lossy(&download::get(client, &file.url)?).into_owned(),
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Download, verify and swap in a release in place of the binary at `exe`
+fn replace(client: &dyn HttpClient, exe: &Path, release: &Release) -> Result<(), Error> {
+    let asset = asset(&release.assets, OS, ARCH).ok_or_else(|| {
+        Error::Update(format!(
+            "release {} has no binary for {} on {}",
+            release.tag, OS, ARCH
+        ))
+    })?;
+    let expected = checksum(client, release, asset)?;
+    let public_key = config::config()?.verify.public_key;
+    let signed = Published {
+        checksum: None,
+        signature: signature(client, release, asset, &public_key)?,
+    };
+
+    // Download next to the running binary, so it can be renamed into place
+    let dir = exe
+        .parent()
+        .ok_or_else(|| Error::Update(format!("{} is not in a directory", exe.display())))?;
+    let staging = dir.join(format!(".leap-update-{}", std::process::id()));
+    std::fs::create_dir_all(&staging).map_err(|e| Error::io(&staging, e))?;
+    let replaced = verified(client, &staging, asset, &expected, &signed, &public_key)
+        .and_then(|downloaded| unpack(&staging, asset, &downloaded))
+        .and_then(|binary| swap(exe, &binary));
+    if let Err(e) = std::fs::remove_dir_all(&staging) {
+        log::warn!("unable to remove {} because {}", staging.display(), e);
+    }
+    replaced
+}
+
+/// Download an asset to `staging` and check it has the `expected` checksum,
+/// and is `signed` with `public_key` when that is set
+fn verified(
+    client: &dyn HttpClient,
+    staging: &Path,
+    asset: &Asset,
+    expected: &str,
+    signed: &Published,
+    public_key: &str,
+) -> Result<PathBuf, Error> {
+    say!("Downloading {}", asset.name);
+    let downloaded = staging.join(&asset.name);
+    download::to_file(client, &asset.url, &downloaded)?;
+    let data = std::fs::read(&downloaded).map_err(|e| Error::io(&downloaded, e))?;
+    let actual = lock::hash(&data);
+    if actual.trim_start_matches("sha256:") != expected {
+        return Err(Error::Update(format!(
+            "the checksum of {} is {} rather than the published {}",
+            asset.name,
+            actual.trim_start_matches("sha256:"),
+            expected
+        )));
+    }
+    verify::published(&downloaded, &data, signed, public_key)?;
+    Ok(downloaded)
+}
+
+/// Unpack a downloaded asset, returning the binary inside it
+fn unpack(staging: &Path, asset: &Asset, downloaded: &Path) -> Result<PathBuf, Error> {
+    let name = asset.name.to_lowercase();
+    let unpacked = staging.join("unpacked");
+    if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        tar::extract(downloaded, &unpacked)?;
+    } else if name.ends_with(".zip") {
+        zip::extract(downloaded, &unpacked)?;
+    } else {
+        return Ok(downloaded.to_path_buf());
+    }
+
+    let binary = format!("{}{}", env!("CARGO_PKG_NAME"), std::env::consts::EXE_SUFFIX);
+    fs::files(&unpacked)
+        .map_err(|e| Error::io(&unpacked, e))?
+        .into_iter()
+        .find(|path| path.file_name().is_some_and(|name| name == binary.as_str()))
+        .map(|path| unpacked.join(path))
+        .ok_or_else(|| Error::Update(format!("{} does not contain {}", asset.name, binary)))
+}
+
+/// Atomically put `binary` in the place of `exe`
+fn swap(exe: &Path, binary: &Path) -> Result<(), Error> {
+    let permissions = std::fs::metadata(exe)
+        .map_err(|e| Error::io(exe, e))?
+        .permissions();
+    std::fs::set_permissions(binary, permissions).map_err(|e| Error::io(binary, e))?;
+
+    // Windows will not replace a running executable, but does let it be moved
+    if cfg!(windows) {
+        let old = exe.with_extension("old.exe");
+        let _ = std::fs::remove_file(&old);
+        std::fs::rename(exe, &old).map_err(|e| Error::io(exe, e))?;
+        if let Err(e) = std::fs::rename(binary, exe) {
+            // Put the running leap back rather than leave nothing there
+            if let Err(back) = std::fs::rename(&old, exe) {
+                log::warn!(
+                    "unable to move {} back to {} because {}",
+                    old.display(),
+                    exe.display(),
+                    back
+                );
+            }
+            return Err(Error::io(exe, e));
+        }
+        return Ok(());
+    }
+    std::fs::rename(binary, exe).map_err(|e| Error::io(exe, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+
+    fn asset(name: &str) -> Asset {
+        Asset {
+            name: name.to_string(),
+            url: String::new(),
+        }
+    }
+
+    #[test]
+    fn asset_works() {
+        let assets = vec![
+            asset("leap-x86_64-linux.tar.gz.sha256"),
+            asset("leap-x86_64-linux.tar.gz"),
+            asset("leap-aarch64-apple-darwin.tar.gz"),
+            asset("leap-x86_64-pc-windows-msvc.zip"),
+            asset("leap-armv7-unknown-linux-gnueabihf.tar.gz"),
+            asset("leap_arm_linux.tar.gz"),
+            asset("SHA256SUMS"),
+        ];
+        let name = |os, arch| super::asset(&assets, os, arch).map(|asset| asset.name.as_str());
+        assert_eq!(name("linux", "x86_64"), Some("leap-x86_64-linux.tar.gz"));
+        assert_eq!(
+            name("macos", "aarch64"),
+            Some("leap-aarch64-apple-darwin.tar.gz")
+        );
+        assert_eq!(
+            name("windows", "x86_64"),
+            Some("leap-x86_64-pc-windows-msvc.zip")
+        );
+        assert_eq!(name("linux", "aarch64"), None);
+        assert_eq!(name("linux", "x86"), None);
+        assert_eq!(name("windows", "x86"), None);
+        assert_eq!(name("linux", "arm"), Some("leap_arm_linux.tar.gz"));
+        assert_eq!(words("leap_x86_64_linux"), ["leap", "x86_64", "linux"]);
+
+        let list =
+            "0123abcd  leap-x86_64-linux.tar.gz\nABCDEF01 *leap-x86_64-pc-windows-msvc.zip\n";
+        assert_eq!(
+            listed(list, "leap-x86_64-linux.tar.gz").as_deref(),
+            Some("0123abcd")
+        );
+        assert_eq!(
+            listed(list, "leap-x86_64-pc-windows-msvc.zip").as_deref(),
+            Some("abcdef01")
+        );
+        assert_eq!(listed(list, "leap.zip"), None);
+    }
+
+    #[test]
+    fn verified_works() {
+        use crate::cli::http::{Mock, Response};
+        use crate::cli::verify::{DATA, PUBLIC_KEY, SIGNATURE};
+
+        let staging = scratch("verified");
+        let client = Mock::new().route("http://a.test/leap-x86_64-linux", Response::ok(DATA));
+        let download = Asset {
+            name: "leap-x86_64-linux".to_string(),
+            url: "http://a.test/leap-x86_64-linux".to_string(),
+        };
+        let hash = lock::hash(DATA);
+        let expected = hash.trim_start_matches("sha256:");
+        let signed = Published {
+            checksum: None,
+            signature: Some(SIGNATURE.to_string()),
+        };
+        let unsigned = Published::default();
+
+        let verify = |expected: &str, signed: &Published, public_key: &str| {
+            verified(&client, &staging, &download, expected, signed, public_key)
+        };
+        assert!(verify(expected, &unsigned, "").is_ok());
+        assert!(verify(expected, &signed, PUBLIC_KEY).is_ok());
+        let refused = verify("0123abcd", &signed, PUBLIC_KEY).unwrap_err();
+        assert!(refused.to_string().contains("rather than the published"));
+        let refused = verify(expected, &unsigned, PUBLIC_KEY).unwrap_err();
+        assert!(refused.to_string().contains("not signed"));
+    }
+
+    #[test]
+    fn summary_works() {
+        let notes =
+            "## What's new\r\n\r\n- one\n- two\n\n### Fixes\n- three\n- four\n- five\n- six";
+        assert_eq!(
+            summary(notes),
+            vec!["- one", "- two", "- three", "- four", "- five", "..."]
+        );
+        assert!(summary("").is_empty());
+    }
+
+    #[test]
+    fn installed_by_cargo_works() {
+        let home = Path::new("/home/someone/.cargo");
+        assert!(installed_by_cargo(
+            Path::new("/home/someone/.cargo/bin/leap"),
+            home
+        ));
+        assert!(!installed_by_cargo(Path::new("/usr/local/bin/leap"), home));
+    }
+}
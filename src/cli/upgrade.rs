@@ -0,0 +1,433 @@
+//! Upgrade a project to the latest version of the template
+//!
+//! Every template file is merged three ways: the template the project was
+//! generated from is the common ancestor, the project holds the user's
+//! changes, and the latest template holds the upstream changes.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+
+use crate::cli::config::TemplateConfig;
+use crate::cli::error::Error;
+use crate::cli::github::Tag;
+use crate::cli::http::HttpClient;
+use crate::cli::lock::{self, Lock, TemplateLock};
+use crate::cli::render::Variables;
+use crate::cli::source::Source;
+use crate::cli::{config, fs, github, manifest, project, template};
+
+/// Options for the upgrade sub-command
+#[derive(Debug, Clone, Default)]
+pub struct UpgradeOptions {
+    /// The template version the project was generated from, when it has no lock file
+    pub from: Option<Version>,
+    /// Report what would change without touching any files
+    pub dry_run: bool,
+    /// Values for template variables given with `--define key=value`
+    pub defines: Vec<(String, String)>,
+}
+
+/// What happened to a project file
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    /// The file is new in the template
+    Added,
+    /// The file was taken from the template as it had not been changed
+    Updated,
+    /// The file was removed from the template
+    Removed,
+    /// Changes from the project and the template were merged cleanly
+    Merged,
+    /// Both changed the file, conflicts need to be resolved by hand
+    Conflict,
+    /// Both changed the file in a way which cannot be merged, nothing was written
+    Skipped(String),
+}
+
+/// The result of an upgrade
+pub struct Upgrade {
+    pub from: Version,
+    pub to: Version,
+    pub changes: Vec<(PathBuf, Change)>,
+}
+
+/// How a single file is merged
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// Leave the project file as it is
+    Keep,
+    /// Replace the project file with the template's, or remove it
+    Take(Option<Vec<u8>>),
+    /// Write merged contents
+    Merged(Vec<u8>),
+    /// Write contents with conflict markers
+    Conflict(Vec<u8>),
+    /// Move the project file to `.orig` and take the template's
+    Replace(Vec<u8>),
+    /// Leave the project file as it is and report why
+    Skip(&'static str),
+}
+
+/// Upgrade the project containing the current directory
+pub fn run(client: &dyn HttpClient, options: &UpgradeOptions) -> Result<Upgrade, Error> {
+    let root = project::root()?;
+    let mut lock = match lock::read(&root) {
+        Ok(lock) => lock,
+        Err(_) if options.from.is_some() => Lock::new(&name(&root), &TemplateConfig::default()),
+        Err(e) => {
+            return Err(Error::Project(format!(
+                "unable to read {} because {}, use --from <version> to say which template version the project was generated from",
+                lock::FILE_NAME,
+                e
+            )))
+        }
+    };
+    if let Some(from) = &options.from {
+        lock.template.version = from.clone();
+    }
+
+    let repository = match lock.template.source.as_str() {
+        "" => github::PROJECT_TEMPLATE_REPOSITORY.to_string(),
+        source => match source.parse() {
+            Ok(Source::Repository { name, .. }) => name,
+            _ => {
+                return Err(Error::Project(format!(
+                "{} is not a released template, only templates released on GitHub can be upgraded",
+                source
+            )))
+            }
+        },
+    };
+    let config = config::settings()?;
+    let versions = github::get_template_versions(client, &config.github.api_url, &repository)?;
+    let sha = Some(lock.template.sha.as_str()).filter(|_| options.from.is_none());
+    let base_tag = base_tag(&versions, sha, &lock.template.version).ok_or_else(|| {
+        Error::Template(format!(
+            "version {} of the project template cannot be found",
+            lock.template.version
+        ))
+    })?;
+    let new_tag = github::latest(&versions, None, false).ok_or_else(|| {
+        Error::Template("the project template has not been released yet".to_string())
+    })?;
+
+    let (_, base) = template::fetch(client, &config.github.api_url, &repository, base_tag)?;
+    let (latest, new) = template::fetch(client, &config.github.api_url, &repository, new_tag)?;
+    let from = lock.template.version.clone();
+    if latest.version <= from {
+        return Ok(Upgrade {
+            from,
+            to: latest.version,
+            changes: Vec::new(),
+        });
+    }
+
+    if lock.variables.is_empty() {
+        lock.variables = unrecorded(&base, &lock.project.name, &options.defines)?;
+    }
+    let ancestors = rendered(&base, &lock.variables)?;
+    // Variables added to the template since are asked for, or given their defaults
+    manifest::read(&new)?.resolve(
+        &mut lock.variables,
+        &options.defines,
+        manifest::interactive(),
+    )?;
+    let updates = rendered(&new, &lock.variables)?;
+    let paths: BTreeSet<&PathBuf> = ancestors.keys().chain(updates.keys()).collect();
+
+    let mut changes = Vec::new();
+    let mut generated = Lock::new(&lock.project.name, &latest);
+    for relative in paths {
+        let ancestor = ancestors.get(relative);
+        let theirs = updates.get(relative);
+        let path = root.join(relative);
+        let ours = if path.is_file() {
+            Some(std::fs::read(&path).map_err(|e| Error::io(&path, e))?)
+        } else {
+            None
+        };
+
+        if let Some(theirs) = theirs {
+            generated = generated.with_file(relative, theirs);
+        }
+
+        let outcome = merge(
+            ancestor.map(Vec::as_slice),
+            ours.as_deref(),
+            theirs.map(Vec::as_slice),
+        );
+        let change = apply(&path, ours.is_some(), outcome, options.dry_run)
+            .map_err(|e| Error::io(&path, e))?;
+        if let Some(change) = change {
+            changes.push((relative.clone(), change));
+        }
+    }
+
+    if !options.dry_run {
+        lock.template = TemplateLock::from(&latest);
+        lock.files = generated.files;
+        lock::write(&root, &lock)?;
+    }
+
+    Ok(Upgrade {
+        from,
+        to: latest.version,
+        changes,
+    })
+}
+
+/// The tag a project was generated from, the one whose commit starts with
+/// `sha`, which may be short, or else the one released as `version`
+fn base_tag<'a>(versions: &'a [Tag], sha: Option<&str>, version: &Version) -> Option<&'a Tag> {
+    let sha = sha.filter(|sha| !sha.is_empty());
+    sha.and_then(|sha| versions.iter().find(|tag| tag.sha.starts_with(sha)))
+        .or_else(|| {
+            versions
+                .iter()
+                .find(|tag| Version::parse(&tag.name).ok().as_ref() == Some(version))
+        })
+}
+
+/// The variables a project whose lock does not record them was generated
+/// with. Only those which follow from its name can be worked out, the rest
+/// have to be given with `--define`, as rendering the template with today's
+/// values would report changes which were never made.
+fn unrecorded(base: &Path, name: &str, defines: &[(String, String)]) -> Result<Variables, Error> {
+    let manifest = manifest::read(base)?;
+    let defined = |key: &str| defines.iter().any(|(name, _)| name == key);
+    let missing: Vec<&str> = ["author", "year"]
+        .iter()
+        .copied()
+        .chain(manifest.variables.iter().map(|v| v.name.as_str()))
+        .chain(manifest.features.iter().map(|f| f.name.as_str()))
+        .filter(|key| !defined(key))
+        .collect();
+    if !missing.is_empty() {
+        return Err(Error::Project(format!(
+            "{} does not record the values the project was generated with, give them with {}",
+            lock::FILE_NAME,
+            missing
+                .iter()
+                .map(|key| format!("--define {}=<value>", key))
+                .collect::<Vec<_>>()
+                .join(" ")
+        )));
+    }
+
+    let mut variables = template::variables(name);
+    // Values for variables the template only declares since are kept for later
+    let known: Vec<(String, String)> = defines
+        .iter()
+        .filter(|(key, _)| {
+            variables.contains_key(key)
+                || manifest.variables.iter().any(|v| &v.name == key)
+                || manifest.features.iter().any(|f| &f.name == key)
+        })
+        .cloned()
+        .collect();
+    manifest.resolve(&mut variables, &known, false)?;
+    Ok(variables)
+}
+
+/// Render every file of a template, keyed by its path in the project
+fn rendered(template: &Path, variables: &Variables) -> Result<BTreeMap<PathBuf, Vec<u8>>, Error> {
+    let manifest = manifest::read(template)?;
+    let mut variables = variables.clone();
+    manifest.resolve(&mut variables, &[], false)?;
+
+    let mut files = BTreeMap::new();
+    for relative in fs::files(template).map_err(|e| Error::io(template, e))? {
+        if !manifest.includes(&relative, &variables) {
+            continue;
+        }
+        if let Some(target) = template::render_path(&relative, &variables)? {
+            let (contents, _) = template::read_file(template, &relative)?;
+            let contents = template::render(&variables, &target, contents)?;
+            files.insert(target, contents);
+        }
+    }
+    Ok(files)
+}
+
+/// Fall back to the directory name when a project has no lock file
+fn name(root: &Path) -> String {
+    root.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Decide how to merge a file given the original template, the project and the new template
+fn merge(ancestor: Option<&[u8]>, ours: Option<&[u8]>, theirs: Option<&[u8]>) -> Outcome {
+    if theirs == ancestor || ours == theirs {
+        return Outcome::Keep;
+    }
+    if ours == ancestor {
+        return Outcome::Take(theirs.map(|theirs| theirs.to_vec()));
+    }
+
+    let (ours, theirs) = match (ours, theirs) {
+        (Some(ours), Some(theirs)) => (ours, theirs),
+        (None, Some(_)) => {
+            return Outcome::Skip("removed from the project but changed in the template")
+        }
+        (Some(_), None) => {
+            return Outcome::Skip("removed from the template but changed in the project")
+        }
+        (None, None) => return Outcome::Keep,
+    };
+
+    let text = |contents: &[u8]| std::str::from_utf8(contents).ok().map(String::from);
+    match (text(ancestor.unwrap_or_default()), text(ours), text(theirs)) {
+        (Some(ancestor), Some(ours), Some(theirs)) => {
+            match diffy::merge(&ancestor, &ours, &theirs) {
+                Ok(merged) => Outcome::Merged(merged.into_bytes()),
+                Err(conflicted) => Outcome::Conflict(conflicted.into_bytes()),
+            }
+        }
+        _ => Outcome::Replace(theirs.to_vec()),
+    }
+}
+
+/// Write the outcome of a merge to the project
+fn apply(
+    path: &Path,
+    exists: bool,
+    outcome: Outcome,
+    dry_run: bool,
+) -> Result<Option<Change>, std::io::Error> {
+    let (contents, change) = match outcome {
+        Outcome::Keep => return Ok(None),
+        Outcome::Skip(reason) => return Ok(Some(Change::Skipped(reason.to_string()))),
+        Outcome::Take(None) => {
+            if !dry_run {
+                std::fs::remove_file(path)?;
+            }
+            return Ok(Some(Change::Removed));
+        }
+        Outcome::Take(Some(contents)) if exists => (contents, Change::Updated),
+        Outcome::Take(Some(contents)) => (contents, Change::Added),
+        Outcome::Merged(contents) => (contents, Change::Merged),
+        Outcome::Conflict(contents) => (contents, Change::Conflict),
+        Outcome::Replace(contents) => {
+            if !dry_run {
+                std::fs::rename(path, orig(path))?;
+            }
+            (contents, Change::Conflict)
+        }
+    };
+
+    if !dry_run {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, contents)?;
+    }
+
+    Ok(Some(change))
+}
+
+/// Where the project's version of a file is kept when it cannot be merged
+fn orig(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".orig");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+    use crate::cli::render::Value;
+
+    #[test]
+    fn unrecorded_works() {
+        let base = scratch("unrecorded");
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(
+            base.join(manifest::FILE_NAME),
+            "[[features]]\nname = \"database\"\n",
+        )
+        .unwrap();
+
+        let missing = unrecorded(&base, "demo", &[]).unwrap_err().to_string();
+        assert!(missing.contains("--define author=<value> --define year=<value>"));
+        assert!(missing.contains("--define database=<value>"));
+
+        let define = |key: &str, value: &str| (key.to_string(), value.to_string());
+        let defines = vec![
+            define("author", "Ada"),
+            define("year", "2019"),
+            define("database", "yes"),
+            define("added_since", "x"),
+        ];
+        let variables = unrecorded(&base, "demo", &defines).unwrap();
+        assert_eq!(variables["year"], "2019".into());
+        assert_eq!(variables["author"], "Ada".into());
+        assert_eq!(variables["database"], Value::Bool(true));
+        assert_eq!(variables["project_name"], "demo".into());
+        assert!(!variables.contains_key("added_since"));
+    }
+
+    #[test]
+    fn base_tag_works() {
+        let tag = |name: &str, sha: &str| Tag {
+            name: name.to_string(),
+            tag: name.to_string(),
+            zipball: String::new(),
+            tarball: String::new(),
+            sha: sha.to_string(),
+        };
+        let versions = vec![
+            tag("0.3.0", "9f8e7d6c5b4a39281706f5e4d3c2b1a098765432"),
+            tag("0.2.0", "6cdba5e0123456789abcdef0123456789abcdef0"),
+        ];
+        let name = |sha, version: &str| {
+            base_tag(&versions, sha, &Version::parse(version).unwrap()).map(|tag| &tag.name[..])
+        };
+        assert_eq!(name(Some("6cdba5e"), "0.3.0"), Some("0.2.0"));
+        assert_eq!(
+            name(Some("9f8e7d6c5b4a39281706f5e4d3c2b1a098765432"), "0.2.0"),
+            Some("0.3.0")
+        );
+        // A commit which is not tagged any more, or was never recorded
+        assert_eq!(name(Some("1a2b3c4"), "0.3.0"), Some("0.3.0"));
+        assert_eq!(name(Some(""), "0.2.0"), Some("0.2.0"));
+        assert_eq!(name(None, "0.2.0"), Some("0.2.0"));
+        assert_eq!(name(Some("1a2b3c4"), "0.1.0"), None);
+    }
+
+    #[test]
+    fn merge_works() {
+        let base: &[u8] = b"a\nb\nc\n";
+        let changed: &[u8] = b"a\nB\nc\n";
+
+        assert_eq!(merge(Some(base), Some(changed), Some(base)), Outcome::Keep);
+        assert_eq!(
+            merge(Some(base), Some(changed), Some(changed)),
+            Outcome::Keep
+        );
+        assert_eq!(
+            merge(Some(base), Some(base), Some(changed)),
+            Outcome::Take(Some(changed.to_vec()))
+        );
+        assert_eq!(merge(Some(base), Some(base), None), Outcome::Take(None));
+        assert_eq!(
+            merge(Some(base), Some(b"A\nb\nc\n"), Some(b"a\nb\nC\n")),
+            Outcome::Merged(b"A\nb\nC\n".to_vec())
+        );
+        assert!(matches!(
+            merge(Some(base), Some(b"a\nX\nc\n"), Some(b"a\nY\nc\n")),
+            Outcome::Conflict(_)
+        ));
+        assert_eq!(
+            merge(Some(base), Some(b"\xff\x00"), Some(changed)),
+            Outcome::Replace(changed.to_vec())
+        );
+        assert!(matches!(
+            merge(Some(base), None, Some(changed)),
+            Outcome::Skip(_)
+        ));
+    }
+}
@@ -0,0 +1,198 @@
+//! Checking downloaded templates
+//!
+//! The SHA-256 of each archive is recorded in the cache when it is
+//! downloaded and checked again whenever the archive is used, so an archive
+//! which has changed since is refused rather than extracted.
+//!
+//! Templates may also publish a checksum and a minisign signature of their
+//! archive:
+//!
+//! - next to a URL, as `<url>.sha256` and `<url>.minisig`
+//! - on the GitHub release of a tag, as the assets `source.zip.sha256` and
+//!   `source.zip.minisig` for the tag's zipball
+//!
+//! A published checksum has to match. When `verify.public_key` is set in the
+//! global config, templates have to be signed with that key.
+
+use std::path::Path;
+
+use minisign_verify::{PublicKey, Signature};
+
+use crate::cli::error::Error;
+use crate::cli::http::HttpClient;
+use crate::cli::{download, github, lock};
+
+/// What a tag's zipball is called among the assets of its release
+pub const SOURCE_ZIP: &str = "source.zip";
+
+/// What a template's publisher says its archive is
+#[derive(Debug, Clone, Default)]
+pub struct Published {
+    /// A `sha256sum` style checksum
+    pub checksum: Option<String>,
+    /// A minisign signature
+    pub signature: Option<String>,
+}
+
+impl Published {
+    /// The checksum and signature published next to `url`
+    pub fn at(client: &dyn HttpClient, url: &str) -> Result<Published, Error> {
+        Ok(Published {
+            checksum: text(client, &format!("{}.sha256", url))?,
+            signature: text(client, &format!("{}.minisig", url))?,
+        })
+    }
+
+    /// The checksum and signature attached to the release of `tag`, if it has one
+    pub fn released(
+        client: &dyn HttpClient,
+        api_url: &str,
+        repository: &str,
+        tag: &str,
+    ) -> Result<Published, Error> {
+        let release = match github::get_release(client, api_url, repository, tag)? {
+            Some(release) => release,
+            None => return Ok(Published::default()),
+        };
+        let asset = |extension: &str| -> Result<Option<String>, Error> {
+            let name = format!("{}.{}", SOURCE_ZIP, extension);
+            match release.assets.iter().find(|asset| asset.name == name) {
+                Some(asset) => text(client, &asset.url),
+                None => Ok(None),
+            }
+        };
+        Ok(Published {
+            checksum: asset("sha256")?,
+            signature: asset("minisig")?,
+        })
+    }
+}
+
+/// A small file which may not be there
+fn text(client: &dyn HttpClient, url: &str) -> Result<Option<String>, Error> {
+    Ok(download::find(client, url)?.map(|data| String::from_utf8_lossy(&data).into_owned()))
+}
+
+/// Check the archive downloaded to `path` is the one which was published,
+/// signed with `public_key` when it is set
+pub fn published(
+    path: &Path,
+    data: &[u8],
+    published: &Published,
+    public_key: &str,
+) -> Result<(), Error> {
+    let refuse = |message: String| Error::Archive {
+        path: path.to_path_buf(),
+        message,
+    };
+
+    if let Some(checksum) = &published.checksum {
+        let expected = checksum
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        let hash = lock::hash(data);
+        let actual = hash.trim_start_matches("sha256:");
+        if actual != expected {
+            return Err(refuse(format!(
+                "its checksum is {} rather than the published {}",
+                actual, expected
+            )));
+        }
+        log::info!("{} matches its published checksum", path.display());
+    }
+
+    let public_key = public_key.trim();
+    if public_key.is_empty() {
+        return Ok(());
+    }
+    let key = PublicKey::from_base64(public_key).map_err(|e| {
+        Error::Config(format!(
+            "verify.public_key is not a minisign public key, {}",
+            e
+        ))
+    })?;
+    let signature = published.signature.as_deref().ok_or_else(|| {
+        refuse("it is not signed, and verify.public_key says it must be".to_string())
+    })?;
+    let signature = Signature::decode(signature)
+        .map_err(|e| refuse(format!("its signature cannot be read, {}", e)))?;
+    key.verify(data, &signature, false).map_err(|e| {
+        refuse(format!(
+            "its signature does not match verify.public_key, {}",
+            e
+        ))
+    })?;
+    log::info!("{} is signed with verify.public_key", path.display());
+    Ok(())
+}
+
+/// Check the archive at `path` still has the checksum `recorded` when it was downloaded
+pub fn cached(path: &Path, recorded: &str) -> Result<(), Error> {
+    let data = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+    let actual = lock::hash(&data);
+    if actual != recorded {
+        return Err(Error::Archive {
+            path: path.to_path_buf(),
+            message: format!(
+                "it has changed since it was downloaded, its checksum is {} rather than {}. \
+                 Remove it with leap cache clean",
+                actual, recorded
+            ),
+        });
+    }
+    Ok(())
+}
+
+/// Is `hash` a checksum, rather than the file name older versions of leap recorded?
+pub fn is_checksum(hash: &str) -> bool {
+    hash.starts_with("sha256:")
+}
+
+/// A minisign public key, and its signature of [`DATA`], for tests
+#[cfg(test)]
+pub const PUBLIC_KEY: &str = "RWRMRUFQAQIDBKItKmifIIlV7ZQoxLB3iWv40JYA6Pgc+6jrE4qb7JUX";
+#[cfg(test)]
+pub const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RURMRUFQAQIDBIii3P3wTQNjlUkXU4Vj7On87tceOGT47vt1I7tOPtpOJ8M3H/o0/crdSxNJUTH/xYb1X9MeI5w2Ql6lhImm+gk=
+trusted comment: timestamp:1760000000\tfile:source.zip\thashed
+JuUHeqnTcjcCsg+mdXgJV6lkMLzhve2Itx7MWj/t3Ja7TbcxPE3KHTL9d1U5A49FShT0FAezHw5YLDKYLsVNAg==
+";
+#[cfg(test)]
+pub const DATA: &[u8] = b"the leap project template\n";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn published_works() {
+        let path = Path::new("source.zip");
+        let hash = lock::hash(DATA);
+        let checksum = format!("{}  source.zip\n", hash.trim_start_matches("sha256:"));
+        let signed = Published {
+            checksum: Some(checksum.clone()),
+            signature: Some(SIGNATURE.to_string()),
+        };
+        assert!(published(path, DATA, &signed, "").is_ok());
+        assert!(published(path, DATA, &signed, PUBLIC_KEY).is_ok());
+        assert!(published(path, DATA, &Published::default(), "").is_ok());
+
+        let tampered = b"the leap project template\nrm -rf /\n";
+        let refused = published(path, tampered, &signed, "").unwrap_err();
+        assert!(refused.to_string().contains("rather than the published"));
+        let unsigned = Published {
+            checksum: None,
+            ..signed.clone()
+        };
+        let refused = published(path, tampered, &unsigned, PUBLIC_KEY).unwrap_err();
+        assert!(refused.to_string().contains("signature does not match"));
+        let refused = published(path, DATA, &Published::default(), PUBLIC_KEY).unwrap_err();
+        assert!(refused.to_string().contains("not signed"));
+        assert!(matches!(
+            published(path, DATA, &signed, "not a key"),
+            Err(Error::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,392 @@
+//! Rebuild and restart a leap project when its files change
+//!
+//! Unless live reload is turned off, browsers are refreshed after every
+//! successful restart, and stylesheets are swapped in place when the only
+//! files changed are CSS files outside of `src`.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+
+use crate::cli::error::Error;
+use crate::cli::reload::LiveReload;
+use crate::cli::serve::{self, ServeOptions, Server};
+use crate::cli::{cargo, project};
+
+/// Directories, relative to the project root, which trigger a rebuild
+const WATCHED_DIRECTORIES: [&str; 6] = [
+    "src",
+    "templates",
+    "config",
+    "assets",
+    "resources",
+    "public",
+];
+/// Files, relative to the project root, which trigger a rebuild
+const WATCHED_FILES: [&str; 2] = ["Cargo.toml", "build.rs"];
+/// How long the file system has to be quiet before a rebuild starts
+const DEBOUNCE: Duration = Duration::from_millis(300);
+/// How often to check for Ctrl-C while waiting for changes
+const POLL: Duration = Duration::from_millis(100);
+/// How long to wait for a restarted application before reloading browsers
+const STARTUP: Duration = Duration::from_secs(10);
+
+type Events = Receiver<Result<Event, notify::Error>>;
+
+/// How to bring the running project up to date with changed files
+#[derive(Debug, PartialEq)]
+enum Update {
+    /// Swap the stylesheets in open pages
+    Stylesheets,
+    /// Build the project again and restart it
+    Rebuild,
+}
+
+/// What a build means for the running project
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// Restart with this executable
+    Restart(PathBuf),
+    /// The build succeeded without building a binary
+    NoBinary,
+    /// The build failed, its errors have been printed
+    Failed,
+}
+
+/// Serve the project, rebuilding and restarting it whenever a watched file changes
+pub fn run(options: &ServeOptions) -> Result<(), Error> {
+    serve::handle_interrupts();
+    let root = project::root()?;
+
+    let (tx, events) = channel();
+    let unwatchable =
+        |e: notify::Error| Error::Project(format!("unable to watch for changes because {}", e));
+    let mut watcher = notify::recommended_watcher(tx).map_err(unwatchable)?;
+    for path in watched(&root) {
+        log::info!("watching: {:?}", &path);
+        let mode = if path.is_dir() {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        watcher.watch(&path, mode).map_err(unwatchable)?;
+    }
+
+    let reload = if options.live_reload {
+        let reload = LiveReload::start(&options.host, options.port).map_err(|e| {
+            Error::Project(format!(
+                "unable to listen on {}:{} because {}",
+                options.host, options.port, e
+            ))
+        })?;
+        Some(reload)
+    } else {
+        None
+    };
+    // Behind the live reload proxy the application only listens locally
+    let app_options = match &reload {
+        Some(reload) => ServeOptions {
+            host: "127.0.0.1".to_string(),
+            port: reload.upstream(),
+            ..options.clone()
+        },
+        None => options.clone(),
+    };
+
+    say!("Watching {} for changes", root.display());
+    let mut server = rebuild(&root, &app_options, None);
+    if server.is_some() {
+        started(options, &reload);
+    }
+
+    while !serve::interrupted() {
+        match events.recv_timeout(POLL) {
+            Ok(event) => {
+                let mut changed = relevant(&event);
+                if changed.is_empty() {
+                    continue;
+                }
+                debounce(&events, &mut changed, DEBOUNCE);
+                if serve::interrupted() {
+                    break;
+                }
+
+                if let Some(reload) = &reload {
+                    if update(&root, &changed) == Update::Stylesheets {
+                        say!("Stylesheets changed, reloading...");
+                        reload.reload_css();
+                        continue;
+                    }
+                }
+
+                say!("Change detected, rebuilding...");
+                let running = server.as_ref().map(Server::id);
+                server = rebuild(&root, &app_options, server);
+                if server.is_some() && server.as_ref().map(Server::id) != running {
+                    started(options, &reload);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) if !serve::interrupted() => {
+                if let Some(running) = server.as_mut() {
+                    if let Some(status) = running.try_wait()? {
+                        say!("The project exited with {}, waiting for changes...", status);
+                        server = None;
+                    }
+                }
+            }
+            Err(_) => break,
+        }
+    }
+
+    if let Some(mut running) = server {
+        running.stop()?;
+    }
+
+    Ok(())
+}
+
+/// Announce a freshly started application and refresh connected browsers
+fn started(options: &ServeOptions, reload: &Option<LiveReload>) {
+    say!(
+        "Serving on http://{}:{} ({})",
+        options.host,
+        options.port,
+        options.environment
+    );
+    if let Some(reload) = reload {
+        if reload.wait_for_upstream(STARTUP) {
+            reload.reload();
+        } else {
+            log::error!("the project is not accepting connections yet");
+        }
+    }
+}
+
+/// The watched paths which exist in the project
+fn watched(root: &Path) -> Vec<PathBuf> {
+    WATCHED_DIRECTORIES
+        .iter()
+        .chain(WATCHED_FILES.iter())
+        .map(|path| root.join(path))
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// The paths changed by this event which are worth rebuilding for
+fn relevant(event: &Result<Event, notify::Error>) -> Vec<PathBuf> {
+    match event {
+        Ok(event) => {
+            let changed = matches!(
+                event.kind,
+                EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+            );
+            if !changed {
+                return Vec::new();
+            }
+            event
+                .paths
+                .iter()
+                .filter(|path| !scratch_file(path))
+                .cloned()
+                .collect()
+        }
+        Err(e) => {
+            log::error!("unable to watch for changes because {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// How the `changed` files can be applied: swapping stylesheets does when
+/// they are all CSS files outside of `src`, anything else needs a rebuild
+fn update(root: &Path, changed: &[PathBuf]) -> Update {
+    let src = root.join("src");
+    let stylesheets = changed.iter().all(|path| {
+        path.extension().is_some_and(|extension| extension == "css") && !path.starts_with(&src)
+    });
+    if stylesheets {
+        Update::Stylesheets
+    } else {
+        Update::Rebuild
+    }
+}
+
+/// Editor swap and backup files
+fn scratch_file(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    name.ends_with('~')
+        || name.ends_with(".swp")
+        || name.ends_with(".swx")
+        || name.starts_with(".#")
+        || name == "4913"
+}
+
+/// Wait for a burst of changes to settle down, until nothing has changed for
+/// `window`, collecting the changed paths
+fn debounce(events: &Events, changed: &mut Vec<PathBuf>, window: Duration) {
+    while !serve::interrupted() {
+        match events.recv_timeout(window) {
+            Ok(event) => changed.extend(relevant(&event)),
+            Err(_) => break,
+        }
+    }
+}
+
+/// Build the project and swap in a new server if the build succeeds.
+///
+/// When the build fails the compiler errors have already been printed and
+/// the previous server keeps running.
+fn rebuild(root: &Path, options: &ServeOptions, server: Option<Server>) -> Option<Server> {
+    let build = match cargo::build(root, options.release) {
+        Ok(build) => build,
+        Err(e) => {
+            log::error!("unable to build project because {}", e);
+            return server;
+        }
+    };
+
+    if serve::interrupted() {
+        return server;
+    }
+
+    let executable = match outcome(build) {
+        Outcome::Restart(executable) => executable,
+        Outcome::NoBinary => {
+            log::error!("the project does not have a binary target");
+            return server;
+        }
+        Outcome::Failed => {
+            if server.is_some() {
+                say!("Build failed, the previous version is still running");
+            } else {
+                say!("Build failed, waiting for changes...");
+            }
+            return server;
+        }
+    };
+
+    if let Some(mut running) = server {
+        if let Err(e) = running.stop() {
+            log::error!("unable to stop the previous version because {}", e);
+        }
+    }
+
+    match Server::start(root, &executable, options) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            log::error!("unable to start project because {}", e);
+            None
+        }
+    }
+}
+
+/// Whether to restart the project after `build`
+fn outcome(build: cargo::Build) -> Outcome {
+    match (build.success, build.executable) {
+        (true, Some(executable)) => Outcome::Restart(executable),
+        (true, None) => Outcome::NoBinary,
+        (false, _) => Outcome::Failed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::extract::scratch;
+    use notify::event::{AccessKind, CreateKind, ModifyKind};
+    use std::time::Instant;
+
+    fn event(kind: EventKind, path: &str) -> Result<Event, notify::Error> {
+        Ok(Event::new(kind).add_path(PathBuf::from(path)))
+    }
+
+    #[test]
+    fn relevant_works() {
+        let modify = EventKind::Modify(ModifyKind::Any);
+        assert_eq!(
+            relevant(&event(modify, "/app/src/main.rs")),
+            [PathBuf::from("/app/src/main.rs")]
+        );
+        assert_eq!(
+            relevant(&event(EventKind::Create(CreateKind::File), "/app/a")).len(),
+            1
+        );
+        assert!(relevant(&event(EventKind::Access(AccessKind::Any), "/app/a")).is_empty());
+        for scratch in &["main.rs~", ".main.rs.swp", ".#main.rs", "4913"] {
+            let path = format!("/app/src/{}", scratch);
+            assert!(relevant(&event(modify, &path)).is_empty(), "{}", scratch);
+        }
+        assert!(relevant(&Err(notify::Error::generic("gone"))).is_empty());
+    }
+
+    #[test]
+    fn update_works() {
+        let root = Path::new("/app");
+        let update = |changed: &[&str]| {
+            let changed: Vec<PathBuf> = changed.iter().map(|path| root.join(path)).collect();
+            super::update(root, &changed)
+        };
+        assert_eq!(update(&["public/app.css"]), Update::Stylesheets);
+        assert_eq!(
+            update(&["assets/a.css", "resources/b.css"]),
+            Update::Stylesheets
+        );
+        assert_eq!(update(&["src/style.css"]), Update::Rebuild);
+        assert_eq!(update(&["public/app.css", "src/main.rs"]), Update::Rebuild);
+        assert_eq!(update(&["templates/index.html"]), Update::Rebuild);
+    }
+
+    #[test]
+    fn debounce_works() {
+        let (tx, events) = channel();
+        let window = Duration::from_millis(200);
+        let sender = std::thread::spawn(move || {
+            for (i, pause) in [50, 50, 1000].iter().enumerate() {
+                std::thread::sleep(Duration::from_millis(*pause));
+                let path = format!("/app/src/{}.rs", i);
+                let _ = tx.send(event(EventKind::Modify(ModifyKind::Any), &path));
+            }
+        });
+
+        // A burst of changes is taken together, a later one is left for next time
+        let start = Instant::now();
+        let mut changed = Vec::new();
+        debounce(&events, &mut changed, window);
+        assert_eq!(changed.len(), 2);
+        assert!(start.elapsed() >= Duration::from_millis(100) + window);
+        assert!(events.recv().is_ok());
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn outcome_works() {
+        let build = |success, executable: Option<&str>| cargo::Build {
+            success,
+            executable: executable.map(PathBuf::from),
+        };
+        assert_eq!(
+            outcome(build(true, Some("target/debug/app"))),
+            Outcome::Restart(PathBuf::from("target/debug/app"))
+        );
+        assert_eq!(outcome(build(true, None)), Outcome::NoBinary);
+        assert_eq!(
+            outcome(build(false, Some("target/debug/app"))),
+            Outcome::Failed
+        );
+    }
+
+    #[test]
+    fn watched_works() {
+        let dir = scratch("watched");
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::create_dir_all(dir.join("target")).unwrap();
+        std::fs::write(dir.join("Cargo.toml"), "").unwrap();
+        assert_eq!(watched(&dir), [dir.join("src"), dir.join("Cargo.toml")]);
+    }
+}
@@ -1,52 +1,94 @@
-pub fn extract(path: &std::path::Path, extract_to: &std::path::Path) -> Result<(), String> {
-    let file = std::fs::File::open(&path).unwrap();
+//! Extracting .zip archives, see [`extract`](crate::cli::extract) for what
+//! is checked
 
-    let mut archive = zip::ZipArchive::new(file).unwrap();
+use std::path::Path;
 
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
-        let outpath = extract_to.join(file.sanitized_name());
+use crate::cli::error::Error;
+use crate::cli::extract::{self, ExtractError, Limits, Unpacker};
 
-        {
-            let comment = file.comment();
-            if !comment.is_empty() {
-                log::info!("File {} comment: {}", i, comment);
-            }
+/// The file type bits of a unix mode, and the type of a symlink
+const FILE_TYPE: u32 = 0o170_000;
+const SYMLINK: u32 = 0o120_000;
+
+/// Extract the archive at `path` to `extract_to`, within the configured limits
+pub fn extract(path: &Path, extract_to: &Path) -> Result<(), Error> {
+    extract_with(path, extract_to, Limits::configured()?)
+}
+
+/// Extract the archive at `path` to `extract_to`, which must not exist yet
+pub fn extract_with(path: &Path, extract_to: &Path, limits: Limits) -> Result<(), Error> {
+    extract::staged(path, extract_to, |file, staging| {
+        unpack(file, staging, limits)
+    })
+}
+
+/// Extract every entry of an archive into `to`
+fn unpack(file: std::fs::File, to: &Path, limits: Limits) -> Result<(), ExtractError> {
+    let unreadable = |e: zip::result::ZipError| ExtractError::Unreadable(e.to_string());
+    let mut archive = zip::ZipArchive::new(file).map_err(unreadable)?;
+    if archive.len() > limits.entries {
+        return Err(ExtractError::TooManyEntries {
+            limit: limits.entries,
+        });
+    }
+
+    let mut unpacker = Unpacker::new(to, limits)?;
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(unreadable)?;
+        unpacker.count()?;
+        let outpath = match unpacker.path(file.name())? {
+            Some(outpath) => outpath,
+            None => continue,
+        };
+        let mode = file.unix_mode();
+        if mode.is_some_and(|mode| mode & FILE_TYPE == SYMLINK) {
+            return Err(ExtractError::Link(file.name().to_string()));
         }
 
-        if (&*file.name()).ends_with('/') {
-            log::info!(
-                "File {} extracted to \"{}\"",
-                i,
-                outpath.as_path().display()
-            );
-            std::fs::create_dir_all(&outpath).unwrap();
+        if file.is_dir() {
+            unpacker.dir(&outpath, mode)?;
         } else {
-            log::info!(
-                "File {} extracted to \"{}\" ({} bytes)",
-                i,
-                outpath.as_path().display(),
-                file.size()
-            );
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    std::fs::create_dir_all(&p).unwrap();
-                }
-            }
-            let mut outfile = std::fs::File::create(&outpath).unwrap();
-            std::io::copy(&mut file, &mut outfile).unwrap();
+            let size = file.size();
+            unpacker.file(&outpath, size, &mut file, mode)?;
         }
+    }
 
-        // Get and Set permissions
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
+    Ok(())
+}
 
-            if let Some(mode) = file.unix_mode() {
-                std::fs::set_permissions(&outpath, std::fs::Permissions::from_mode(mode)).unwrap();
+/// A zip of `entries` for tests, with the unix modes the writer will not set
+#[cfg(test)]
+pub fn archive(entries: &[extract::Entry<'_>]) -> Vec<u8> {
+    use extract::Entry;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    let mut writer = ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let mut modes = Vec::new();
+    for entry in entries {
+        let (name, contents, mode) = match *entry {
+            Entry::Dir(name) => {
+                writer.add_directory(name, FileOptions::default()).unwrap();
+                modes.push(0o040_755);
+                continue;
             }
-        }
+            Entry::File(name, contents, mode) => (name, contents, 0o100_000 | mode),
+            Entry::Symlink(name, target) => (name, target.as_bytes(), SYMLINK | 0o777),
+        };
+        writer.start_file(name, FileOptions::default()).unwrap();
+        writer.write_all(contents).unwrap();
+        modes.push(mode);
     }
 
-    Ok(())
+    let mut bytes = writer.finish().unwrap().into_inner();
+    let headers: Vec<usize> = bytes
+        .windows(4)
+        .enumerate()
+        .filter(|(_, signature)| *signature == b"PK\x01\x02")
+        .map(|(offset, _)| offset)
+        .collect();
+    for (offset, mode) in headers.into_iter().zip(modes) {
+        bytes[offset + 38..offset + 42].copy_from_slice(&(mode << 16).to_le_bytes());
+    }
+    bytes
 }
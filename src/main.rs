@@ -1,109 +1,639 @@
-use std::env::args;
+use std::collections::BTreeSet;
+use std::io::IsTerminal;
+use std::path::PathBuf;
 
-use leap::cli::{new_project, serve_project, update_cli, upgrade_project, watch_project};
+use clap::error::{ContextKind, ContextValue, ErrorKind};
+use clap::{ArgAction, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::engine::{
+    ArgValueCandidates, ArgValueCompleter, CompletionCandidate, PathCompleter, ValueCompleter,
+};
+use clap_complete::env::{CompleteEnv, Shells};
+use semver::{Version, VersionReq};
+
+use leap::cli::{
+    self, cache, cached_releases, config, new_project, report_error, serve_project, update_cli,
+    upgrade_project, version_requirement, watch_project, CacheCommand, ConfigCommand, Error,
+    HookPolicy, NewOptions, OutputOptions, ServeOptions, Source, UpdateOptions, UpgradeOptions,
+    PROJECT_TEMPLATE_REPOSITORY,
+};
+
+/// Heading of the flags every sub-command takes
+const GLOBAL: &str = "Global options";
+
+const MORE_INFORMATION: &str =
+    "See https://leap.rs/book/version/master/getting-started/installation/ for more information.";
+
+const COMPLETIONS_HELP: &str = "\
+Load the completions when the shell starts, so they keep up with leap:
+
+  bash        echo 'source <(leap completions bash)' >> ~/.bashrc
+  zsh         echo 'source <(leap completions zsh)' >> ~/.zshrc
+  fish        echo 'leap completions fish | source' >> ~/.config/fish/config.fish
+  elvish      echo 'eval (leap completions elvish | slurp)' >> ~/.config/elvish/rc.elv
+  powershell  Add-Content $PROFILE 'leap completions powershell | Out-String | Invoke-Expression'
+
+Template versions are completed from the templates in leap cache list.
+
+See https://leap.rs/book/version/master/getting-started/installation/ for more information.";
+
+const CONFIG_HELP: &str = "\
+Keys are the sections and names in the config file joined with dots, such as
+template.version or github.api_url. Values are checked before they are saved.
+
+The new and serve settings and update.refresh can also be set for a project in
+leap.toml, found in the current directory or its parents, and in LEAP_*
+environment variables such as LEAP_SERVE_PORT for serve.port. Each overrides
+the one before it, and command line flags override them all. leap config set
+and unset change the global config. The github settings are only read from the
+global config, so a project cannot send your token elsewhere.
+
+Config files written by older versions of leap are migrated when they are
+read, keeping a copy of the old file next to it.
+
+See https://leap.rs/book/version/master/getting-started/installation/ for more information.";
+
+const NEW_HELP: &str = "\
+Template sources:
+  ./path/to/template         A directory
+  template.zip               A .zip or .tar.gz file
+  https://host/template.zip  A .zip or .tar.gz file to download
+  owner/repo[@tag]           A release of a GitHub repository, set github.api_url
+                             in the config to use a GitHub compatible server
+
+Templates may declare variables and features in leap-template.toml. Any which
+are not given with --define are asked for when running in a terminal, or take
+their default value otherwise.
+
+Templates other than the leap project template may only run the commands in
+their hooks once you agree to them. Without a terminal to ask on, or with
+--json, they are skipped unless --allow-hooks is given.
+
+The project template is checked for a newer release every 180 days, set
+update.refresh in the config to an interval such as 12h, 30d or 2w, to always
+or to never. When the network cannot be reached the newest cached template is
+used.
+
+Downloaded templates are checked against the SHA-256 checksum they publish,
+next to their URL as <url>.sha256 or as the source.zip.sha256 asset of their
+release, and cached templates against the checksum recorded when they were
+downloaded. Set verify.public_key in the config to a minisign public key to
+only accept templates signed with it, as <url>.minisig or source.zip.minisig.
+Archives with more entries than extract.max_entries, or holding more than
+extract.max_size bytes, are refused.
+
+See https://leap.rs/book/version/master/getting-started/installation/ for more information.";
+
+const UPDATE_HELP: &str = "\
+The latest release of leap is downloaded for this platform, checked against
+its published SHA-256 checksum and put in the place of the running leap. When
+verify.public_key is set in the config, the release also has to be signed
+with that key, as <asset>.minisig. When leap was installed with cargo install,
+the cargo command updating it is printed instead.
+
+See https://leap.rs/book/version/master/getting-started/installation/ for more information.";
+
+const UPGRADE_HELP: &str = "\
+Changes made to the template since the project was generated are merged with
+the changes made to the project. Where both changed the same lines, conflict
+markers are written to the file. Files which cannot be merged are moved to
+<file>.orig and replaced with the latest version.
+
+See https://leap.rs/book/version/master/prologue/upgrade-guide/ for more information.";
+
+const SERVE_HELP: &str = "\
+The host, port and environment are passed to the application as the
+--host, --port and --env flags and the LEAP_HOST, LEAP_PORT and LEAP_ENV
+environment variables. Anything after -- is passed through unchanged.
+
+See https://leap.rs/book/version/master/getting-started/installation/ for more information.";
+
+const WATCH_HELP: &str = "\
+Changes to src, templates, config, assets, resources, public, Cargo.toml and
+build.rs rebuild the project. The application is only restarted when the
+build succeeds, otherwise the previous version keeps running.
+
+Pages are served through a proxy which refreshes open browsers after each
+restart. Changes to CSS files outside of src are applied without a rebuild.
+
+See https://leap.rs/book/version/master/getting-started/installation/ for more information.";
+
+/// Leap command line tool
+#[derive(Parser, Debug)]
+#[command(
+    name = "leap",
+    version,
+    after_help = "See https://leap.rs/book/version/master/introduction/ for more information."
+)]
+struct Cli {
+    /// Prints more details of what leap is doing, repeat for even more
+    #[arg(short, long, action = ArgAction::Count, global = true, help_heading = GLOBAL)]
+    verbose: u8,
+
+    /// Only prints results and errors
+    #[arg(short, long, global = true, conflicts_with = "verbose", help_heading = GLOBAL)]
+    quiet: bool,
+
+    /// When to use colors
+    #[arg(
+        long,
+        value_enum,
+        value_name = "when",
+        default_value_t = Color::Auto,
+        global = true,
+        help_heading = GLOBAL
+    )]
+    color: Color,
+
+    /// Reads and writes the config at this path
+    #[arg(long, value_name = "path", global = true, help_heading = GLOBAL)]
+    config: Option<PathBuf>,
+
+    /// Prints results as JSON
+    #[arg(long, global = true, help_heading = GLOBAL)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Creates a new project
+    #[command(after_help = NEW_HELP)]
+    New(NewArgs),
+    /// Updates leap and the project template to their latest versions
+    #[command(after_help = UPDATE_HELP)]
+    Update(UpdateArgs),
+    /// Upgrades the project to the latest version of the template
+    #[command(after_help = UPGRADE_HELP)]
+    Upgrade(UpgradeArgs),
+    /// Builds and runs the project
+    #[command(after_help = SERVE_HELP)]
+    Serve(ServeArgs),
+    /// Serves the project and reloads it on changes
+    #[command(after_help = WATCH_HELP)]
+    Watch(WatchArgs),
+    /// Lists and cleans downloaded templates
+    #[command(subcommand, after_help = MORE_INFORMATION)]
+    Cache(CacheArgs),
+    /// Reads and changes the config
+    #[command(subcommand, after_help = CONFIG_HELP)]
+    Config(ConfigArgs),
+    /// Prints the script which completes leap commands in a shell
+    #[command(after_help = COMPLETIONS_HELP)]
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+#[derive(Args, Debug)]
+struct NewArgs {
+    /// The name of the project and its directory
+    #[arg(value_name = "project name")]
+    name: String,
+
+    /// Sets a template variable or feature, may be repeated
+    #[arg(short = 'D', long = "define", value_name = "key=value", value_parser = define)]
+    defines: Vec<(String, String)>,
+
+    /// Generates the project from another template
+    #[arg(short, long, value_name = "source", add = ArgValueCompleter::new(template_sources))]
+    template: Option<Source>,
+
+    /// Uses the newest release matching the version, e.g. 0.3.1 for exactly
+    /// 0.3.1 or ^0.3 for any 0.3 release
+    #[arg(
+        long,
+        value_name = "version",
+        value_parser = version_requirement,
+        add = ArgValueCandidates::new(|| cached_versions(None))
+    )]
+    template_version: Option<VersionReq>,
+
+    /// Includes prereleases when choosing a release
+    #[arg(long)]
+    pre: bool,
+
+    /// Uses the newest cached template without checking for updates, see
+    /// leap cache list
+    #[arg(long)]
+    offline: bool,
+
+    /// Checks for a newer release of the project template, however recently
+    /// it was checked, see update.refresh in the config
+    #[arg(long, conflicts_with = "offline")]
+    refresh: bool,
+
+    /// Runs the template's commands without asking, otherwise they are only
+    /// run when confirmed in a terminal
+    #[arg(long)]
+    allow_hooks: bool,
+
+    /// Never runs the template's commands
+    #[arg(long, conflicts_with = "allow_hooks")]
+    no_hooks: bool,
+}
+
+#[derive(Args, Debug)]
+struct UpdateArgs {
+    /// Shows whether a newer version is available without installing it
+    #[arg(long)]
+    check: bool,
+}
+
+#[derive(Args, Debug)]
+struct UpgradeArgs {
+    /// The template version the project was generated from, for projects
+    /// without a leap.lock file
+    #[arg(long, value_name = "version")]
+    from: Option<Version>,
+
+    /// Shows what would change without changing any files
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Sets a template variable or feature, may be repeated. Needed for
+    /// variables the template has added, unless asked for in a terminal, and
+    /// for projects whose leap.lock does not record their values
+    #[arg(short = 'D', long = "define", value_name = "key=value", value_parser = define)]
+    defines: Vec<(String, String)>,
+}
+
+#[derive(Args, Debug)]
+struct ServeArgs {
+    /// The address to listen on [default: serve.host in the config]
+    #[arg(long, value_name = "host")]
+    host: Option<String>,
+
+    /// The port to listen on [default: serve.port in the config]
+    #[arg(short, long, value_name = "port")]
+    port: Option<u16>,
+
+    /// The environment to run in [default: serve.environment in the config]
+    #[arg(short, long = "env", value_name = "env")]
+    environment: Option<String>,
+
+    /// Builds and runs with optimisations
+    #[arg(long)]
+    release: bool,
+
+    /// Passed to the application unchanged
+    #[arg(last = true, value_name = "app arguments")]
+    args: Vec<String>,
+}
+
+#[derive(Args, Debug)]
+struct WatchArgs {
+    #[command(flatten)]
+    serve: ServeArgs,
+
+    /// Does not refresh browsers after a rebuild
+    #[arg(long)]
+    no_reload: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheArgs {
+    /// Lists the cached templates with their versions, sizes and dates
+    List,
+    /// Removes cached templates
+    Clean {
+        /// Keeps the n most recently fetched templates
+        #[arg(long, value_name = "n", default_value_t = 0)]
+        keep: usize,
+    },
+    /// Downloads a release of the project template ahead of time
+    Prefetch {
+        /// The release to download, e.g. 0.3.1 or ^0.3
+        #[arg(
+            value_name = "version",
+            value_parser = version_requirement,
+            add = ArgValueCandidates::new(|| cached_versions(Some(PROJECT_TEMPLATE_REPOSITORY)))
+        )]
+        version: VersionReq,
+
+        /// Includes prereleases
+        #[arg(long)]
+        pre: bool,
+    },
+    /// Prints where templates are cached
+    Path,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigArgs {
+    /// Prints the value of a key
+    Get {
+        /// A dotted key, e.g. template.version
+        key: String,
+    },
+    /// Changes the value of a key
+    Set { key: String, value: String },
+    /// Puts a key back to its default value
+    Unset { key: String },
+    /// Prints every key and its value
+    List {
+        /// Shows whether each value is the default, or comes from the global
+        /// config, a project's leap.toml or the environment
+        #[arg(long)]
+        show_origin: bool,
+    },
+    /// Prints where the config file is
+    Path,
+    /// Opens the config file in $VISUAL or $EDITOR
+    Edit,
+    /// Upgrades a config file written by an older version of leap
+    Migrate {
+        /// Shows what would change without changing the config
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+/// Shells leap can complete commands in
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Elvish,
+    Powershell,
+}
+
+/// When to use colors
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
 
 /// Main entry point for the command line tool
 fn main() {
-    env_logger::init();
-    match args().nth(1usize) {
-        None => print_help(),
-        Some(a) => match a.as_ref() {
-            "new" => run_subcommand_new(),
-            "update" => run_subcommand_update(),
-            "upgrade" => run_subcommand_upgrade(),
-            "serve" => run_subcommand_serve(),
-            "watch" => run_subcommand_watch(),
-            _ => print_help(),
+    // Answers the completion scripts, which run leap with COMPLETE set
+    CompleteEnv::with_factory(Cli::command).complete();
+
+    let args = Cli::try_parse().unwrap_or_else(|e| usage(suggest(e)));
+    let color = match args.color {
+        Color::Auto => std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        Color::Always => true,
+        Color::Never => false,
+    };
+    init_logger(&args, color);
+    cli::init(
+        OutputOptions {
+            quiet: args.quiet,
+            json: args.json,
+            color,
         },
+        args.config,
+    );
+
+    let result = match args.command {
+        Command::New(new) => new_project(NewOptions {
+            name: new.name,
+            defines: new.defines,
+            template: new.template,
+            version: new.template_version,
+            pre: new.pre,
+            offline: new.offline,
+            refresh: new.refresh,
+            hooks: if new.allow_hooks {
+                HookPolicy::Allow
+            } else if new.no_hooks {
+                HookPolicy::Skip
+            } else {
+                HookPolicy::Ask
+            },
+        }),
+        Command::Update(update) => update_cli(UpdateOptions {
+            check: update.check,
+        }),
+        Command::Upgrade(upgrade) => upgrade_project(UpgradeOptions {
+            from: upgrade.from,
+            dry_run: upgrade.dry_run,
+            defines: upgrade.defines,
+        }),
+        Command::Serve(serve) => serve_options(serve).and_then(serve_project),
+        Command::Watch(watch) => {
+            let live_reload = !watch.no_reload;
+            serve_options(watch.serve).and_then(|options| {
+                watch_project(ServeOptions {
+                    live_reload,
+                    ..options
+                })
+            })
+        }
+        Command::Cache(command) => cache(match command {
+            CacheArgs::List => CacheCommand::List,
+            CacheArgs::Clean { keep } => CacheCommand::Clean { keep },
+            CacheArgs::Prefetch { version, pre } => CacheCommand::Prefetch { version, pre },
+            CacheArgs::Path => CacheCommand::Path,
+        }),
+        Command::Config(command) => config(match command {
+            ConfigArgs::Get { key } => ConfigCommand::Get { key },
+            ConfigArgs::Set { key, value } => ConfigCommand::Set { key, value },
+            ConfigArgs::Unset { key } => ConfigCommand::Unset { key },
+            ConfigArgs::List { show_origin } => ConfigCommand::List { show_origin },
+            ConfigArgs::Path => ConfigCommand::Path,
+            ConfigArgs::Edit => ConfigCommand::Edit,
+            ConfigArgs::Migrate { dry_run } => ConfigCommand::Migrate { dry_run },
+        }),
+        Command::Completions { shell } => completions(shell),
+    };
+
+    if let Err(e) = result {
+        report_error(&e);
+        std::process::exit(e.exit_code());
     }
 }
 
-/// Prints the help text
-fn print_help() {
-    println!(include_str!("help.txt"));
-}
+/// Log as much as `--verbose` and `--quiet` ask for. Without either, the
+/// `RUST_LOG` environment variable is used, falling back to warnings.
+///
+/// `-v` and `-vv` only add details from leap itself, `-vvv` traces everything.
+fn init_logger(args: &Cli, color: bool) {
+    use log::LevelFilter;
 
-/// Perform the new sub-command
-fn run_subcommand_new() {
-    match args().nth(2usize) {
-        None => print_help_subcommand_new(),
-        Some(arg) => match arg.as_ref() {
-            "--help" => print_help_subcommand_new(),
-            "-h" => print_help_subcommand_new(),
-            _ => new_project(arg),
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(LevelFilter::Warn);
+    match (args.quiet, args.verbose) {
+        (true, _) => builder.filter_level(LevelFilter::Error),
+        (false, 0) => match std::env::var("RUST_LOG") {
+            Ok(filters) => builder.parse_filters(&filters),
+            Err(_) => &mut builder,
         },
-    }
+        (false, 1) => builder.filter_module("leap", LevelFilter::Info),
+        (false, 2) => builder.filter_module("leap", LevelFilter::Debug),
+        (false, _) => builder.filter_level(LevelFilter::Trace),
+    };
+    builder.write_style(if color {
+        env_logger::WriteStyle::Always
+    } else {
+        env_logger::WriteStyle::Never
+    });
+    builder.init();
 }
 
-/// Prints the help text for the new sub-command
-fn print_help_subcommand_new() {
-    println!(include_str!("help_subcommand_new.txt"));
+/// Print the script registering leap's completions with `shell`
+fn completions(shell: Shell) -> Result<(), Error> {
+    let name = shell
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+        .unwrap_or_default();
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(&name)
+        .ok_or_else(|| Error::Usage(format!("{} completions are not supported", name)))?;
+    let mut script = Vec::new();
+    completer.write_registration("COMPLETE", "leap", "leap", "leap", &mut script)?;
+    cli::completions(&name, String::from_utf8_lossy(&script).into_owned())
 }
 
-/// Perform the update sub-command
-fn run_subcommand_update() {
-    match args().nth(2usize) {
-        Some(arg) => match arg.as_ref() {
-            "--help" => print_help_subcommand_update(),
-            "-h" => print_help_subcommand_update(),
-            _ => update_cli(),
-        },
-        _ => update_cli(),
-    }
+/// Released template versions in the cache, of `source` or of any template
+fn cached_versions(source: Option<&str>) -> Vec<CompletionCandidate> {
+    cached_releases()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(cached, _)| source.is_none_or(|source| source == cached))
+        .map(|(cached, version)| CompletionCandidate::new(version).help(Some(cached.into())))
+        .collect()
 }
 
-/// Prints the help text for the update sub-command
-fn print_help_subcommand_update() {
-    println!(include_str!("help_subcommand_update.txt"));
-}
+/// Template sources to complete: paths, and repositories which are in the cache
+fn template_sources(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
+    let typed = current.to_string_lossy();
+    let repositories: BTreeSet<String> = cached_releases()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(source, _)| source)
+        .filter(|source| source.starts_with(typed.as_ref()) && !source.contains("://"))
+        .collect();
 
-/// Perform the upgrade sub-command
-fn run_subcommand_upgrade() {
-    match args().nth(2usize) {
-        Some(arg) => match arg.as_ref() {
-            "--help" => print_help_subcommand_upgrade(),
-            "-h" => print_help_subcommand_upgrade(),
-            _ => upgrade_project(),
-        },
-        _ => upgrade_project(),
-    }
+    let mut candidates: Vec<CompletionCandidate> = repositories
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect();
+    candidates.extend(PathCompleter::any().complete(current));
+    candidates
 }
 
-/// Prints the help text for the upgrade sub-command
-fn print_help_subcommand_upgrade() {
-    println!(include_str!("help_subcommand_upgrade.txt"));
+/// The options shared by the serve and watch sub-commands, flags which were
+/// not given come from the config
+fn serve_options(args: ServeArgs) -> Result<ServeOptions, Error> {
+    let configured = ServeOptions::configured()?;
+    Ok(ServeOptions {
+        host: args.host.unwrap_or(configured.host),
+        port: args.port.unwrap_or(configured.port),
+        environment: args.environment.unwrap_or(configured.environment),
+        release: args.release,
+        args: args.args,
+        ..configured
+    })
 }
 
-/// Perform the serve sub-command
-fn run_subcommand_serve() {
-    match args().nth(2usize) {
-        Some(arg) => match arg.as_ref() {
-            "--help" => print_help_subcommand_serve(),
-            "-h" => print_help_subcommand_serve(),
-            _ => serve_project(),
+/// Exit on a command line leap does not understand, or after printing help
+fn usage(e: clap::Error) -> ! {
+    // Nothing has been parsed, so look for --json by hand
+    let json = std::env::args()
+        .take_while(|arg| arg != "--")
+        .any(|arg| arg == "--json");
+    if !json || !e.use_stderr() {
+        e.exit();
+    }
+
+    cli::init(
+        OutputOptions {
+            json,
+            ..OutputOptions::default()
         },
-        _ => serve_project(),
+        None,
+    );
+    let rendered = e.render().to_string();
+    let message = rendered.lines().next().unwrap_or_default();
+    let e = Error::Usage(message.trim_start_matches("error: ").to_string());
+    report_error(&e);
+    std::process::exit(e.exit_code());
+}
+
+/// Suggest the sub-command an unknown one was probably meant to be, for
+/// typos clap does not catch itself such as swapped letters
+fn suggest(mut e: clap::Error) -> clap::Error {
+    if e.kind() != ErrorKind::InvalidSubcommand || e.get(ContextKind::SuggestedSubcommand).is_some()
+    {
+        return e;
+    }
+    let typed = match e.get(ContextKind::InvalidSubcommand) {
+        Some(ContextValue::String(typed)) => typed.clone(),
+        _ => return e,
+    };
+
+    let command = Cli::command();
+    let closest = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name())
+        .map(|name| (distance(&typed, name), name))
+        .filter(|(distance, name)| *distance <= name.len() / 3)
+        .min();
+    if let Some((_, name)) = closest {
+        e.insert(
+            ContextKind::SuggestedSubcommand,
+            ContextValue::String(name.to_string()),
+        );
     }
+    e
 }
 
-/// Prints the help text for the serve sub-command
-fn print_help_subcommand_serve() {
-    println!(include_str!("help_subcommand_serve.txt"));
+/// The number of insertions, deletions, substitutions and swaps of
+/// neighbouring letters turning `a` into `b`
+fn distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
 }
 
-/// Perform the watch sub-command
-fn run_subcommand_watch() {
-    match args().nth(2usize) {
-        Some(arg) => match arg.as_ref() {
-            "--help" => print_help_subcommand_watch(),
-            "-h" => print_help_subcommand_watch(),
-            _ => watch_project(),
-        },
-        _ => watch_project(),
+/// Parse a `--define key=value`
+fn define(define: &str) -> Result<(String, String), String> {
+    match define.split_once('=') {
+        Some((key, value)) => Ok((key.trim().to_string(), value.to_string())),
+        None => Err("must be given as key=value".to_string()),
     }
 }
 
-/// Prints the help text for the watch sub-command
-fn print_help_subcommand_watch() {
-    println!(include_str!("help_subcommand_watch.txt"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_works() {
+        assert_eq!(distance("new", "new"), 0);
+        assert_eq!(distance("nwe", "new"), 1);
+        assert_eq!(distance("serv", "serve"), 1);
+        assert_eq!(distance("upgrdae", "upgrade"), 1);
+        assert_eq!(distance("cache", "watch"), 3);
+    }
+
+    #[test]
+    fn cli_works() {
+        Cli::command().debug_assert();
+    }
 }